@@ -0,0 +1,96 @@
+use crate::checksum::longhorn_checksum;
+use crate::error::Error;
+use crate::target::BackupTarget;
+
+// Longhorn keeps everything for a backup target under this directory:
+// backupstore/volumes/xx/yy/<volume>/{volume.cfg,backups/,blocks/}
+const BACKUPSTORE_BASE: &str = "backupstore";
+
+pub fn volumes_prefix(root: &str) -> String {
+    format!("{}{}/volumes/", root, BACKUPSTORE_BASE)
+}
+
+// Where Longhorn puts a volume, hashed so volumes spread over prefixes.
+pub fn volume_dir(root: &str, volume: &str) -> String {
+    let checksum = longhorn_checksum(volume.as_bytes());
+    format!(
+        "{}{}/{}/{}/",
+        volumes_prefix(root),
+        &checksum[0..2],
+        &checksum[2..4],
+        volume
+    )
+}
+
+pub fn backup_cfg_key(volume_dir: &str, backup: &str) -> String {
+    format!("{}backups/backup_{}.cfg", volume_dir, backup)
+}
+
+// The checksum must be one that check_checksum accepts.
+pub fn block_key(volume_dir: &str, checksum: &str) -> String {
+    format!(
+        "{}blocks/{}/{}/{}.blk",
+        volume_dir,
+        &checksum[0..2],
+        &checksum[2..4],
+        checksum
+    )
+}
+
+// Every volume directory in the backupstore, each ending in a slash.
+pub async fn volume_dirs(target: &dyn BackupTarget, root: &str) -> Result<Vec<String>, Error> {
+    let mut volumes = Vec::new();
+    for layer1 in target.list_dirs(&volumes_prefix(root)).await? {
+        for layer2 in target.list_dirs(&layer1).await? {
+            volumes.extend(target.list_dirs(&layer2).await?);
+        }
+    }
+    Ok(volumes)
+}
+
+// Object keys of the backup_*.cfg files of a volume.
+pub async fn backup_cfg_keys(
+    target: &dyn BackupTarget,
+    volume_dir: &str,
+) -> Result<Vec<String>, Error> {
+    let prefix = format!("{}backups/", volume_dir);
+    let mut keys: Vec<_> = target
+        .list_files(&prefix)
+        .await?
+        .into_iter()
+        .map(|(key, _)| key)
+        .collect();
+    keys.retain(|k| k[prefix.len()..].starts_with("backup_") && k.ends_with(".cfg"));
+    Ok(keys)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn volume_dir_matches_longhorn() {
+        assert_eq!(
+            volume_dir("", "pvc-5a7e7b5e-8f4e-4d6c-9a3b-2f1e0d9c8b7a"),
+            "backupstore/volumes/1d/74/pvc-5a7e7b5e-8f4e-4d6c-9a3b-2f1e0d9c8b7a/"
+        );
+        assert_eq!(
+            volume_dir("lh/", "pvc-test"),
+            "lh/backupstore/volumes/7b/52/pvc-test/"
+        );
+    }
+
+    #[test]
+    fn block_and_cfg_keys() {
+        let dir = "backupstore/volumes/7b/52/pvc-test/";
+        let checksum = "07845f9f6994e96c314a48a6c67740a65d91826a9bd39d869412fcc84117bab4";
+        assert_eq!(
+            block_key(dir, checksum),
+            format!("{}blocks/07/84/{}.blk", dir, checksum)
+        );
+        assert_eq!(
+            backup_cfg_key(dir, "backup-1"),
+            "backupstore/volumes/7b/52/pvc-test/backups/backup_backup-1.cfg"
+        );
+    }
+}
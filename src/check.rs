@@ -0,0 +1,245 @@
+use crate::backupstore;
+use crate::compression::CompressionMethod;
+use crate::error::{error_chain, Error, ErrorKind};
+use crate::target::BackupTarget;
+use crate::{check_blocks, load_backup_cfg, BLOCK_SIZE};
+use futures_util::{pin_mut, StreamExt};
+use serde::Serialize;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::time::Instant;
+
+// A block object and the backups whose Blocks refer to it.
+struct BlockRef {
+    compressions: Vec<CompressionMethod>,
+    // Indexes into the list of backups.
+    backups: Vec<usize>,
+}
+
+#[derive(Serialize, Debug)]
+pub struct CheckedBlock {
+    pub key: String,
+    // missing or size.
+    pub problem: &'static str,
+    pub size: Option<u64>,
+    // Keys of the backup_*.cfg files that use the block.
+    pub backups: Vec<String>,
+}
+
+// A backup whose blocks could not be checked because its cfg could not
+// be loaded or does not make sense.
+#[derive(Serialize, Debug)]
+pub struct BadBackup {
+    pub backup: String,
+    // missing or invalid.
+    pub problem: &'static str,
+    pub detail: String,
+}
+
+#[derive(Serialize, Default)]
+pub struct CheckSummary {
+    pub backups: usize,
+    pub blocks: usize,
+    pub listings: usize,
+    pub duration_secs: f64,
+    pub bad_blocks: Vec<CheckedBlock>,
+    pub bad_backups: Vec<BadBackup>,
+}
+
+impl CheckSummary {
+    // What to fail with if any backup is missing blocks or unreadable.
+    pub fn error(&self) -> Option<Error> {
+        let backups: BTreeSet<_> = self
+            .bad_blocks
+            .iter()
+            .flat_map(|b| &b.backups)
+            .chain(self.bad_backups.iter().map(|b| &b.backup))
+            .collect();
+        (!backups.is_empty()).then(|| Error::CheckFailed {
+            bad_blocks: self.bad_blocks.len(),
+            bad_backups: self.bad_backups.len(),
+            backups: backups.len(),
+        })
+    }
+}
+
+// Checks that the blocks of the given backups, as (volume directory,
+// backup cfg key), exist and have plausible sizes without downloading
+// any of them.
+pub async fn check(
+    target: &dyn BackupTarget,
+    backups: &[(String, String)],
+    jobs: usize,
+) -> Result<CheckSummary, Error> {
+    let start = Instant::now();
+
+    // Backups of a volume share most of their blocks, so each block is
+    // looked up once however many backups use it.
+    let mut blocks = BTreeMap::<String, BlockRef>::new();
+    let mut bad_backups = Vec::new();
+    let indexes = futures_util::stream::iter(backups)
+        .map(|(_, key)| load_backup_cfg(target, key))
+        .buffered(jobs)
+        .enumerate();
+    pin_mut!(indexes);
+    while let Some((i, index)) = indexes.next().await {
+        let (volume_dir, backup_cfg) = &backups[i];
+        let checked = index.and_then(|index| {
+            check_blocks(&index.Blocks, index.Size)?;
+            let compression = index.CompressionMethod.parse::<CompressionMethod>()?;
+            Ok((index, compression))
+        });
+        // One bad backup must not hide the rest of the sweep.
+        let (index, compression) = match checked {
+            Ok(checked) => checked,
+            Err(e) => {
+                let problem = match e {
+                    _ if e.kind() == ErrorKind::NotFound => "missing",
+                    Error::Config { .. }
+                    | Error::SkippedData(_)
+                    | Error::InvalidChecksum { .. }
+                    | Error::UnsupportedCompression(_) => "invalid",
+                    // Anything else, such as the target being unreachable,
+                    // says nothing about the backup.
+                    e => return Err(e),
+                };
+                bad_backups.push(BadBackup {
+                    backup: backup_cfg.clone(),
+                    problem,
+                    detail: error_chain(&e),
+                });
+                continue;
+            }
+        };
+        for block in &index.Blocks {
+            let key = backupstore::block_key(volume_dir, &block.BlockChecksum);
+            let block_ref = blocks.entry(key).or_insert_with(|| BlockRef {
+                compressions: Vec::new(),
+                backups: Vec::new(),
+            });
+            if !block_ref.compressions.contains(&compression) {
+                block_ref.compressions.push(compression);
+            }
+            if block_ref.backups.last() != Some(&i) {
+                block_ref.backups.push(i);
+            }
+        }
+    }
+
+    // One listing per blocks/xx/yy/ directory rather than a request per
+    // block.
+    let dirs: BTreeSet<_> = blocks
+        .keys()
+        .map(|key| key[..=key.rfind('/').unwrap()].to_owned())
+        .collect();
+    let listings = futures_util::stream::iter(&dirs)
+        .map(|dir| target.list_files(dir))
+        .buffer_unordered(jobs);
+    pin_mut!(listings);
+    let mut sizes = HashMap::new();
+    while let Some(files) = listings.next().await.transpose()? {
+        sizes.extend(files);
+    }
+
+    let mut summary = CheckSummary {
+        backups: backups.len(),
+        blocks: blocks.len(),
+        listings: dirs.len(),
+        bad_backups,
+        ..Default::default()
+    };
+    for (key, block_ref) in blocks {
+        let size = sizes.get(&key).copied();
+        let problem = match size {
+            None => "missing",
+            Some(size)
+                if !block_ref
+                    .compressions
+                    .iter()
+                    .any(|c| c.stored_len_range(BLOCK_SIZE).contains(&size)) =>
+            {
+                "size"
+            }
+            Some(_) => continue,
+        };
+        summary.bad_blocks.push(CheckedBlock {
+            key,
+            problem,
+            size,
+            backups: block_ref
+                .backups
+                .iter()
+                .map(|&i| backups[i].1.clone())
+                .collect(),
+        });
+    }
+    summary.duration_secs = start.elapsed().as_secs_f64();
+    Ok(summary)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixture::Backupstore;
+
+    #[tokio::test]
+    async fn keeps_going_past_bad_backups() {
+        let store = Backupstore::new("check");
+        let dir = &store.volume_dir;
+        let a = store.put_block(0, &[1; BLOCK_SIZE as usize], CompressionMethod::None);
+        let b = store.put_block(
+            BLOCK_SIZE,
+            &[2; BLOCK_SIZE as usize],
+            CompressionMethod::None,
+        );
+        let c = store.put_block(2 * BLOCK_SIZE, &[3; 512], CompressionMethod::None);
+        std::fs::remove_file(
+            store
+                .root
+                .join(backupstore::block_key(dir, &b.BlockChecksum)),
+        )
+        .unwrap();
+        let good = store.put_backup("good", 2 * BLOCK_SIZE, "none", vec![a.clone()]);
+        let holey = store.put_backup("holey", 3 * BLOCK_SIZE, "none", vec![a, b, c]);
+        let garbled = backupstore::backup_cfg_key(dir, "garbled");
+        store.put(&garbled, b"{");
+        let gone = backupstore::backup_cfg_key(dir, "gone");
+        let backups: Vec<_> = [&good, &garbled, &holey, &gone]
+            .into_iter()
+            .map(|key| (dir.clone(), key.clone()))
+            .collect();
+
+        let summary = check(&store.target, &backups, 2).await.unwrap();
+        assert_eq!(summary.backups, 4);
+        assert_eq!(summary.blocks, 3);
+        let mut bad_blocks: Vec<_> = summary
+            .bad_blocks
+            .iter()
+            .map(|b| (b.problem, b.size, b.backups.clone()))
+            .collect();
+        bad_blocks.sort();
+        assert_eq!(
+            bad_blocks,
+            [
+                ("missing", None, vec![holey.clone()]),
+                ("size", Some(512), vec![holey.clone()]),
+            ]
+        );
+        let bad_backups: Vec<_> = summary
+            .bad_backups
+            .iter()
+            .map(|b| (b.backup.as_str(), b.problem))
+            .collect();
+        assert_eq!(
+            bad_backups,
+            [(garbled.as_str(), "invalid"), (gone.as_str(), "missing")]
+        );
+        assert!(matches!(
+            summary.error(),
+            Some(Error::CheckFailed {
+                bad_blocks: 2,
+                bad_backups: 2,
+                backups: 3,
+            })
+        ));
+    }
+}
@@ -0,0 +1,35 @@
+use serde::Serialize;
+use sha2::{Digest, Sha512};
+
+// Longhorn's util.GetChecksum: the first 64 hex digits of the SHA-512.
+// It is used both for block contents and for hashing volume names.
+pub fn longhorn_checksum(data: &[u8]) -> String {
+    let mut checksum = hex::encode(Sha512::digest(data));
+    checksum.truncate(64);
+    checksum
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum OnMismatch {
+    Fail,
+    Report,
+}
+
+#[derive(Serialize, Debug)]
+pub struct ChecksumMismatch {
+    pub offset: u64,
+    pub expected: String,
+    pub found: String,
+}
+
+impl std::fmt::Display for ChecksumMismatch {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "Checksum mismatch in block at offset {}: expected {}, found {}",
+            self.offset, self.expected, self.found
+        )
+    }
+}
+
+impl std::error::Error for ChecksumMismatch {}
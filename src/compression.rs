@@ -0,0 +1,112 @@
+use flate2::read::GzDecoder;
+use lz4::Decoder;
+use std::io::Read;
+use std::ops::RangeInclusive;
+use std::str::FromStr;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CompressionMethod {
+    None,
+    Gzip,
+    Lz4,
+}
+
+#[derive(Debug)]
+pub struct UnsupportedCompression(String);
+
+impl std::fmt::Display for UnsupportedCompression {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "Unsupported CompressionMethod {:?}, expected one of none, gzip, lz4",
+            self.0
+        )
+    }
+}
+
+impl std::error::Error for UnsupportedCompression {}
+
+impl FromStr for CompressionMethod {
+    type Err = UnsupportedCompression;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "none" => Ok(Self::None),
+            // Backups written before Longhorn recorded a CompressionMethod
+            // were always gzip.
+            "gzip" | "" => Ok(Self::Gzip),
+            "lz4" => Ok(Self::Lz4),
+            _ => Err(UnsupportedCompression(s.to_owned())),
+        }
+    }
+}
+
+impl CompressionMethod {
+    pub fn decompress(self, data: &[u8]) -> std::io::Result<Vec<u8>> {
+        let mut out = Vec::new();
+        match self {
+            Self::None => out.extend_from_slice(data),
+            Self::Gzip => {
+                GzDecoder::new(data).read_to_end(&mut out)?;
+            }
+            Self::Lz4 => {
+                Decoder::new(data)?.read_to_end(&mut out)?;
+            }
+        }
+        Ok(out)
+    }
+
+    // The sizes a stored block of block_size bytes can have. The lower
+    // bounds follow from the best ratio each format can reach (about
+    // 1032:1 for deflate, 255:1 for LZ4); incompressible data grows by a
+    // fraction of a percent plus headers.
+    pub fn stored_len_range(self, block_size: u64) -> RangeInclusive<u64> {
+        match self {
+            Self::None => block_size..=block_size,
+            Self::Gzip => block_size / 1032..=block_size + block_size / 256 + 1024,
+            Self::Lz4 => block_size / 255..=block_size + block_size / 128 + 1024,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixture::compress;
+
+    #[test]
+    fn round_trips() {
+        let data: Vec<u8> = (0..100_000u32).map(|i| (i % 251) as u8).collect();
+        for method in [
+            CompressionMethod::None,
+            CompressionMethod::Gzip,
+            CompressionMethod::Lz4,
+        ] {
+            let stored = compress(method, &data);
+            assert_eq!(method.decompress(&stored).unwrap(), data, "{:?}", method);
+        }
+    }
+
+    #[test]
+    fn rejects_corrupt_data() {
+        let mut stored = compress(CompressionMethod::Gzip, b"longhorn");
+        stored.truncate(stored.len() / 2);
+        assert!(CompressionMethod::Gzip.decompress(&stored).is_err());
+        assert!(CompressionMethod::Lz4.decompress(b"longhorn").is_err());
+    }
+
+    #[test]
+    fn parses_methods() {
+        let parse = |s: &str| s.parse::<CompressionMethod>().ok();
+        assert_eq!(parse("none"), Some(CompressionMethod::None));
+        assert_eq!(parse("gzip"), Some(CompressionMethod::Gzip));
+        assert_eq!(parse("lz4"), Some(CompressionMethod::Lz4));
+        assert_eq!(parse(""), Some(CompressionMethod::Gzip));
+        let e = "zstd".parse::<CompressionMethod>().unwrap_err();
+        assert_eq!(
+            e.to_string(),
+            "Unsupported CompressionMethod \"zstd\", expected one of none, gzip, lz4"
+        );
+        assert_eq!(parse("LZ4"), None);
+    }
+}
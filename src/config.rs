@@ -0,0 +1,80 @@
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+// Models of the JSON files Longhorn writes into the backupstore. Field
+// names follow Longhorn's Go structs. Fields this tool does not know
+// about are kept in `extra` so that nothing is lost on a round trip.
+
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+#[allow(non_snake_case)]
+pub struct BackupBlock {
+    pub Offset: u64,
+    pub BlockChecksum: String,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+#[allow(non_snake_case)]
+#[serde(default)]
+pub struct BackupFile {
+    pub FilePath: String,
+}
+
+// backupstore/volumes/xx/yy/<volume>/backups/backup_<name>.cfg
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+#[allow(non_snake_case)]
+#[serde(default)]
+pub struct BackupCfg {
+    pub Name: String,
+    pub VolumeName: String,
+    pub SnapshotName: String,
+    pub SnapshotCreatedAt: String,
+    pub CreatedTime: String,
+    #[serde(with = "string_u64")]
+    pub Size: u64,
+    pub Labels: Option<BTreeMap<String, String>>,
+    pub IsIncremental: bool,
+    pub CompressionMethod: String,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub Blocks: Vec<BackupBlock>,
+    pub SingleFile: BackupFile,
+    #[serde(flatten)]
+    pub extra: serde_json::Map<String, serde_json::Value>,
+}
+
+// backupstore/volumes/xx/yy/<volume>/volume.cfg
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+#[allow(non_snake_case)]
+#[serde(default)]
+pub struct VolumeCfg {
+    pub Name: String,
+    #[serde(with = "string_u64")]
+    pub Size: u64,
+    pub Labels: Option<BTreeMap<String, String>>,
+    pub CreatedTime: String,
+    pub LastBackupName: String,
+    pub LastBackupAt: String,
+    #[serde(with = "string_u64")]
+    pub BlockCount: u64,
+    pub BackingImageName: String,
+    pub BackingImageChecksum: String,
+    pub CompressionMethod: String,
+    pub StorageClassName: String,
+    pub DataEngine: String,
+    #[serde(flatten)]
+    pub extra: serde_json::Map<String, serde_json::Value>,
+}
+
+// Longhorn marshals its int64 sizes and counts with `json:",string"`.
+mod string_u64 {
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(v: &u64, s: S) -> Result<S::Ok, S::Error> {
+        s.collect_str(v)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(d: D) -> Result<u64, D::Error> {
+        String::deserialize(d)?
+            .parse()
+            .map_err(serde::de::Error::custom)
+    }
+}
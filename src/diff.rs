@@ -0,0 +1,232 @@
+use crate::checksum::longhorn_checksum;
+use crate::error::Error;
+use crate::target::BackupTarget;
+use crate::{check_blocks, load_backup_cfg, sparse_regions, BLOCK_SIZE};
+use futures_util::{pin_mut, StreamExt};
+use serde::Serialize;
+use std::fs::File;
+use std::io::{Seek, SeekFrom};
+use std::os::unix::fs::FileExt;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Instant;
+
+#[derive(Serialize, Debug)]
+pub struct DifferentBlock {
+    pub offset: u64,
+    pub expected: String,
+    pub found: String,
+}
+
+#[derive(Serialize, Debug)]
+pub struct DataRegion {
+    pub offset: u64,
+    pub length: u64,
+}
+
+#[derive(Serialize, Default)]
+pub struct DiffSummary {
+    pub backup: String,
+    pub path: PathBuf,
+    pub blocks: usize,
+    pub blocks_matching: usize,
+    pub different_blocks: Vec<DifferentBlock>,
+    // Non-zero data in the regions the backup has no blocks for.
+    pub extra_data: Vec<DataRegion>,
+    pub duration_secs: f64,
+}
+
+impl DiffSummary {
+    pub fn extra_bytes(&self) -> u64 {
+        self.extra_data.iter().map(|r| r.length).sum()
+    }
+
+    // What to fail with if the local copy does not match.
+    pub fn error(&self) -> Option<Error> {
+        let extra_bytes = self.extra_bytes();
+        (!self.different_blocks.is_empty() || extra_bytes != 0).then(|| Error::Differs {
+            path: self.path.clone(),
+            blocks: self.different_blocks.len(),
+            extra_bytes,
+        })
+    }
+}
+
+fn read_at(file: &File, offset: u64, len: u64) -> std::io::Result<Vec<u8>> {
+    let mut buf = vec![0; len as usize];
+    file.read_exact_at(&mut buf, offset)?;
+    Ok(buf)
+}
+
+// Compares the file or block device at path with the backup at
+// backup_cfg, using only the checksums in its Blocks.
+pub async fn diff(
+    target: &dyn BackupTarget,
+    backup_cfg: &str,
+    path: &Path,
+    jobs: usize,
+) -> Result<DiffSummary, Error> {
+    let start = Instant::now();
+    let index = load_backup_cfg(target, backup_cfg).await?;
+    check_blocks(&index.Blocks, index.Size)?;
+
+    let file = Arc::new(File::open(path).map_err(Error::io(path))?);
+    // Works for block devices too, unlike the metadata's length.
+    let len = (&*file).seek(SeekFrom::End(0)).map_err(Error::io(path))?;
+    if len < index.Size {
+        return Err(Error::Usage(format!(
+            "{} is {} bytes, smaller than the {} byte volume",
+            path.display(),
+            len,
+            index.Size
+        )));
+    }
+
+    let mut summary = DiffSummary {
+        backup: backup_cfg.to_owned(),
+        path: path.to_owned(),
+        blocks: index.Blocks.len(),
+        ..Default::default()
+    };
+    let size = index.Size;
+    let hashes = futures_util::stream::iter(&index.Blocks)
+        .map(|block| {
+            let file = file.clone();
+            async move {
+                let offset = block.Offset;
+                let found = tokio::task::spawn_blocking(move || {
+                    read_at(&file, offset, BLOCK_SIZE.min(size - offset))
+                        .map(|data| longhorn_checksum(&data))
+                })
+                .await?
+                .map_err(Error::io(path))?;
+                Ok::<_, Error>((block, found))
+            }
+        })
+        .buffered(jobs);
+    pin_mut!(hashes);
+    while let Some((block, found)) = hashes.next().await.transpose()? {
+        if found == block.BlockChecksum {
+            summary.blocks_matching += 1;
+            continue;
+        }
+        summary.different_blocks.push(DifferentBlock {
+            offset: block.Offset,
+            expected: block.BlockChecksum.clone(),
+            found,
+        });
+    }
+
+    // The backup says everything else is zero; look at it a block at a
+    // time.
+    let chunks = sparse_regions(&index.Blocks, size)
+        .into_iter()
+        .flat_map(|(offset, length)| {
+            (offset..offset + length)
+                .step_by(BLOCK_SIZE as usize)
+                .map(move |o| (o, BLOCK_SIZE.min(offset + length - o)))
+        });
+    let nonzero = futures_util::stream::iter(chunks)
+        .map(|(offset, length)| {
+            let file = file.clone();
+            async move {
+                let nonzero = tokio::task::spawn_blocking(move || {
+                    read_at(&file, offset, length).map(|data| data.iter().any(|&b| b != 0))
+                })
+                .await?
+                .map_err(Error::io(path))?;
+                Ok::<_, Error>((offset, length, nonzero))
+            }
+        })
+        .buffered(jobs);
+    pin_mut!(nonzero);
+    while let Some((offset, length, nonzero)) = nonzero.next().await.transpose()? {
+        if !nonzero {
+            continue;
+        }
+        match summary.extra_data.last_mut() {
+            Some(region) if region.offset + region.length == offset => region.length += length,
+            _ => summary.extra_data.push(DataRegion { offset, length }),
+        }
+    }
+    summary.duration_secs = start.elapsed().as_secs_f64();
+    Ok(summary)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::BackupBlock;
+    use crate::fixture::{temp_path, Backupstore};
+
+    const BS: u64 = BLOCK_SIZE;
+
+    fn block(offset: u64, data: &[u8]) -> BackupBlock {
+        BackupBlock {
+            Offset: offset,
+            BlockChecksum: longhorn_checksum(data),
+        }
+    }
+
+    #[tokio::test]
+    async fn finds_differences() {
+        let store = Backupstore::new("diff");
+        let size = 5 * BS + 4096;
+        let a = vec![1; BS as usize];
+        let b = vec![2; BS as usize];
+        let cfg = store.put_backup("b", size, "lz4", vec![block(0, &a), block(2 * BS, &b)]);
+
+        // Block 0 matches, block 2 does not, and of the sparse regions
+        // [BS, 2BS) and [3BS, size) only the block at 4BS stays zero.
+        let mut local = vec![0; size as usize];
+        local[..BS as usize].copy_from_slice(&a);
+        local[(2 * BS) as usize] = 3;
+        local[(BS + 5) as usize] = 1;
+        local[(3 * BS + 10) as usize] = 1;
+        local[(5 * BS + 1) as usize] = 1;
+        let path = temp_path("diff.img");
+        std::fs::write(&path, &local).unwrap();
+
+        let summary = diff(&store.target, &cfg, &path, 2).await;
+        let e = diff(&store.target, &cfg, &store.root.join("nothing"), 2).await;
+        std::fs::remove_file(&path).unwrap();
+        let summary = summary.unwrap();
+        assert_eq!((summary.blocks, summary.blocks_matching), (2, 1));
+        assert_eq!(summary.different_blocks.len(), 1);
+        assert_eq!(summary.different_blocks[0].offset, 2 * BS);
+        assert_eq!(
+            summary.different_blocks[0].expected,
+            block(0, &b).BlockChecksum
+        );
+        let extra: Vec<_> = summary
+            .extra_data
+            .iter()
+            .map(|r| (r.offset, r.length))
+            .collect();
+        assert_eq!(extra, [(BS, BS), (3 * BS, BS), (5 * BS, 4096)]);
+        assert_eq!(summary.extra_bytes(), 2 * BS + 4096);
+        assert!(matches!(
+            summary.error(),
+            Some(Error::Differs { blocks: 1, .. })
+        ));
+        assert!(matches!(e, Err(Error::Io { .. })));
+    }
+
+    #[tokio::test]
+    async fn merges_adjacent_regions() {
+        let store = Backupstore::new("diff-merge");
+        let size = 3 * BS;
+        let cfg = store.put_backup("b", size, "lz4", Vec::new());
+        let path = temp_path("diff-merge.img");
+        std::fs::write(&path, vec![1; size as usize]).unwrap();
+        let summary = diff(&store.target, &cfg, &path, 2).await;
+        std::fs::remove_file(&path).unwrap();
+        let extra: Vec<_> = summary
+            .unwrap()
+            .extra_data
+            .iter()
+            .map(|r| (r.offset, r.length))
+            .collect();
+        assert_eq!(extra, [(0, size)]);
+    }
+}
@@ -0,0 +1,309 @@
+use crate::checksum::ChecksumMismatch;
+use crate::compression::UnsupportedCompression;
+use s3::creds::error::CredentialsError;
+use s3::error::S3Error;
+use std::path::{Path, PathBuf};
+
+#[derive(Debug)]
+pub struct SkippedData {
+    pub expected_offset: u64,
+    pub found_offset: u64,
+}
+
+impl std::fmt::Display for SkippedData {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "Gap in data: expected to find a block for offset {}, found {}",
+            self.expected_offset, self.found_offset
+        )
+    }
+}
+
+impl std::error::Error for SkippedData {}
+
+#[derive(Debug)]
+pub enum Error {
+    Usage(String),
+    NotFound(String),
+    Credentials {
+        // Where we looked for them.
+        from: String,
+        source: CredentialsError,
+    },
+    // A request to the backup target failed.
+    S3 {
+        key: String,
+        source: S3Error,
+    },
+    // A request over HTTP other than through rust-s3 got no response.
+    Request {
+        key: String,
+        source: reqwest::Error,
+    },
+    // ... or an unsuccessful one. code is the service's error code.
+    Http {
+        key: String,
+        status: u16,
+        code: String,
+    },
+    // The TLS settings for the backup target could not be applied.
+    Tls {
+        what: &'static str,
+        source: reqwest::Error,
+    },
+    Listing {
+        prefix: String,
+        source: quick_xml::DeError,
+    },
+    // A cfg file in the backupstore could not be parsed.
+    Config {
+        key: String,
+        source: serde_json::Error,
+    },
+    UnsupportedCompression(UnsupportedCompression),
+    Decode {
+        offset: u64,
+        checksum: String,
+        source: std::io::Error,
+    },
+    ChecksumMismatch(ChecksumMismatch),
+    SkippedData(SkippedData),
+    // A BlockChecksum that is not one, and so cannot name a block object.
+    InvalidChecksum {
+        offset: u64,
+        checksum: String,
+    },
+    // verify found blocks that are missing, undecodable or corrupt.
+    VerifyFailed {
+        backup: String,
+        bad_blocks: usize,
+    },
+    // check found blocks that are missing or have implausible sizes, or
+    // backups it could not go through at all.
+    CheckFailed {
+        bad_blocks: usize,
+        bad_backups: usize,
+        // How many backups either problem affects.
+        backups: usize,
+    },
+    // diff found the local copy does not match the backup.
+    Differs {
+        path: PathBuf,
+        blocks: usize,
+        extra_bytes: u64,
+    },
+    JournalMismatch {
+        path: PathBuf,
+        found: String,
+    },
+    // Reading or writing a local file or device failed.
+    Io {
+        path: PathBuf,
+        source: std::io::Error,
+    },
+    Task(tokio::task::JoinError),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ErrorKind {
+    Usage,
+    Auth,
+    NotFound,
+    Corrupt,
+    Io,
+    Differs,
+    Other,
+}
+
+impl ErrorKind {
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Usage => "usage",
+            Self::Auth => "auth",
+            Self::NotFound => "not_found",
+            Self::Corrupt => "corrupt",
+            Self::Io => "io",
+            Self::Differs => "differs",
+            Self::Other => "other",
+        }
+    }
+
+    pub fn exit_code(self) -> u8 {
+        match self {
+            Self::Other => 1,
+            Self::Usage => 2,
+            Self::Auth => 3,
+            Self::NotFound => 4,
+            Self::Corrupt => 5,
+            Self::Io => 6,
+            Self::Differs => 7,
+        }
+    }
+}
+
+impl Error {
+    pub fn kind(&self) -> ErrorKind {
+        match self {
+            Self::Usage(_) | Self::JournalMismatch { .. } | Self::Tls { .. } => ErrorKind::Usage,
+            Self::NotFound(_) => ErrorKind::NotFound,
+            Self::Credentials { .. } => ErrorKind::Auth,
+            Self::S3 { source, .. } => match source {
+                S3Error::HttpFailWithBody(401 | 403, _) | S3Error::Credentials(_) => {
+                    ErrorKind::Auth
+                }
+                S3Error::HttpFailWithBody(404, _) => ErrorKind::NotFound,
+                _ => ErrorKind::Other,
+            },
+            Self::Http { status, .. } => match status {
+                401 | 403 => ErrorKind::Auth,
+                404 => ErrorKind::NotFound,
+                _ => ErrorKind::Other,
+            },
+            Self::Config { .. }
+            | Self::Decode { .. }
+            | Self::ChecksumMismatch(_)
+            | Self::SkippedData(_)
+            | Self::InvalidChecksum { .. }
+            | Self::VerifyFailed { .. }
+            | Self::CheckFailed { .. } => ErrorKind::Corrupt,
+            Self::Io { .. } => ErrorKind::Io,
+            Self::Differs { .. } => ErrorKind::Differs,
+            Self::Request { .. }
+            | Self::Listing { .. }
+            | Self::UnsupportedCompression(_)
+            | Self::Task(_) => ErrorKind::Other,
+        }
+    }
+
+    // For use with map_err on local file operations.
+    pub fn io(path: &Path) -> impl FnOnce(std::io::Error) -> Self + '_ {
+        move |source| Self::Io {
+            path: path.to_owned(),
+            source,
+        }
+    }
+}
+
+// The error and its causes on one line.
+pub fn error_chain(e: &Error) -> String {
+    let mut message = e.to_string();
+    let mut source = std::error::Error::source(e);
+    while let Some(e) = source {
+        message += &format!(": {}", e);
+        source = e.source();
+    }
+    message
+}
+
+impl std::fmt::Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Usage(msg) | Self::NotFound(msg) => f.write_str(msg),
+            Self::Credentials { from, .. } => {
+                write!(f, "Could not load S3 credentials from {}", from)
+            }
+            Self::S3 { key, .. } => write!(f, "Request for {} failed", key),
+            Self::Request { key, .. } => write!(f, "Request for {} failed", key),
+            Self::Http { key, status, code } => {
+                write!(
+                    f,
+                    "Request for {} failed with HTTP {} {}",
+                    key, status, code
+                )
+            }
+            Self::Tls { what, .. } => write!(f, "TLS setup failed for the {}", what),
+            Self::Listing { prefix, .. } => write!(f, "Could not parse listing of {}", prefix),
+            Self::Config { key, .. } => write!(f, "Could not parse {}", key),
+            Self::UnsupportedCompression(e) => e.fmt(f),
+            Self::Decode {
+                offset, checksum, ..
+            } => write!(
+                f,
+                "Could not decompress block {} at offset {}",
+                checksum, offset
+            ),
+            Self::ChecksumMismatch(e) => e.fmt(f),
+            Self::SkippedData(e) => e.fmt(f),
+            Self::InvalidChecksum { offset, checksum } => write!(
+                f,
+                "Block at offset {} has an invalid BlockChecksum {:?}",
+                offset, checksum
+            ),
+            Self::VerifyFailed { backup, bad_blocks } => write!(
+                f,
+                "Backup {} failed verification: {} bad blocks",
+                backup, bad_blocks
+            ),
+            Self::CheckFailed {
+                bad_blocks,
+                bad_backups,
+                backups,
+            } => write!(
+                f,
+                "{} backups failed the check: {} blocks are missing or have implausible sizes \
+                 and {} backup cfgs could not be read",
+                backups, bad_blocks, bad_backups
+            ),
+            Self::Differs {
+                path,
+                blocks,
+                extra_bytes,
+            } => write!(
+                f,
+                "{} differs from the backup in {} blocks and has {} bytes of data where the backup has none",
+                path.display(),
+                blocks,
+                extra_bytes
+            ),
+            Self::JournalMismatch { path, found } => write!(
+                f,
+                "Journal {} belongs to a different restore or destination ({:?})",
+                path.display(),
+                found
+            ),
+            Self::Io { path, .. } => write!(f, "I/O error on {}", path.display()),
+            Self::Task(e) => e.fmt(f),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::Credentials { source, .. } => Some(source),
+            Self::S3 { source, .. } => Some(source),
+            Self::Request { source, .. } => Some(source),
+            Self::Tls { source, .. } => Some(source),
+            Self::Listing { source, .. } => Some(source),
+            Self::Config { source, .. } => Some(source),
+            Self::Decode { source, .. } => Some(source),
+            Self::Io { source, .. } => Some(source),
+            _ => None,
+        }
+    }
+}
+
+impl From<UnsupportedCompression> for Error {
+    fn from(e: UnsupportedCompression) -> Self {
+        Self::UnsupportedCompression(e)
+    }
+}
+
+impl From<ChecksumMismatch> for Error {
+    fn from(e: ChecksumMismatch) -> Self {
+        Self::ChecksumMismatch(e)
+    }
+}
+
+impl From<SkippedData> for Error {
+    fn from(e: SkippedData) -> Self {
+        Self::SkippedData(e)
+    }
+}
+
+impl From<tokio::task::JoinError> for Error {
+    fn from(e: tokio::task::JoinError) -> Self {
+        Self::Task(e)
+    }
+}
@@ -0,0 +1,106 @@
+// A throwaway backupstore in a temporary directory, for tests that go
+// through a whole backup.
+
+use crate::backupstore;
+use crate::checksum::longhorn_checksum;
+use crate::compression::CompressionMethod;
+use crate::config::{BackupBlock, BackupCfg};
+use crate::target::FsTarget;
+use std::io::Write;
+use std::path::PathBuf;
+
+pub const VOLUME: &str = "pvc-test";
+
+pub fn compress(method: CompressionMethod, data: &[u8]) -> Vec<u8> {
+    match method {
+        CompressionMethod::None => data.to_vec(),
+        CompressionMethod::Gzip => {
+            let mut encoder =
+                flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+            encoder.write_all(data).unwrap();
+            encoder.finish().unwrap()
+        }
+        CompressionMethod::Lz4 => {
+            let mut encoder = lz4::EncoderBuilder::new().build(Vec::new()).unwrap();
+            encoder.write_all(data).unwrap();
+            let (out, result) = encoder.finish();
+            result.unwrap();
+            out
+        }
+    }
+}
+
+// A path under the temporary directory that no other test uses.
+pub fn temp_path(name: &str) -> PathBuf {
+    std::env::temp_dir().join(format!(
+        "get-longhorn-backup-{}-{}",
+        std::process::id(),
+        name
+    ))
+}
+
+// Removes itself when dropped.
+pub struct Backupstore {
+    pub root: PathBuf,
+    pub target: FsTarget,
+    pub volume_dir: String,
+}
+
+impl Backupstore {
+    pub fn new(name: &str) -> Self {
+        let root = temp_path(name);
+        let _ = std::fs::remove_dir_all(&root);
+        std::fs::create_dir_all(&root).unwrap();
+        Self {
+            target: FsTarget::new(&root),
+            root,
+            volume_dir: backupstore::volume_dir("", VOLUME),
+        }
+    }
+
+    pub fn put(&self, key: &str, data: &[u8]) {
+        let path = self.root.join(key);
+        std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+        std::fs::write(path, data).unwrap();
+    }
+
+    // Stores data as the block at offset and returns its index entry.
+    pub fn put_block(&self, offset: u64, data: &[u8], method: CompressionMethod) -> BackupBlock {
+        let checksum = longhorn_checksum(data);
+        self.put(
+            &backupstore::block_key(&self.volume_dir, &checksum),
+            &compress(method, data),
+        );
+        BackupBlock {
+            Offset: offset,
+            BlockChecksum: checksum,
+        }
+    }
+
+    // Writes backup_<name>.cfg and returns its key.
+    pub fn put_backup(
+        &self,
+        name: &str,
+        size: u64,
+        compression: &str,
+        blocks: Vec<BackupBlock>,
+    ) -> String {
+        let cfg = BackupCfg {
+            Name: name.to_owned(),
+            VolumeName: VOLUME.to_owned(),
+            Size: size,
+            CompressionMethod: compression.to_owned(),
+            Blocks: blocks,
+            ..Default::default()
+        };
+        let key = backupstore::backup_cfg_key(&self.volume_dir, name);
+        self.put(&key, &serde_json::to_vec(&cfg).unwrap());
+        key
+    }
+}
+
+impl Drop for Backupstore {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.root);
+    }
+}
@@ -0,0 +1,156 @@
+use crate::error::Error;
+use std::collections::HashSet;
+use std::fs::{File, OpenOptions};
+use std::io::{BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+
+// (offset, checksum) of the blocks a journal records as written.
+pub type WrittenBlocks = HashSet<(u64, String)>;
+
+// Record of the blocks a restore has written. The first line identifies
+// the backup; each following line is "<offset> <checksum>" for a block
+// whose data was synced to the destination before the line was written.
+pub struct Journal {
+    path: PathBuf,
+    file: File,
+    pending: String,
+}
+
+impl Journal {
+    pub fn create(path: &Path, header: &str) -> Result<Self, Error> {
+        let mut file = File::create(path).map_err(Error::io(path))?;
+        writeln!(file, "{}", header)
+            .and_then(|()| file.sync_data())
+            .map_err(Error::io(path))?;
+        Ok(Self {
+            path: path.to_owned(),
+            file,
+            pending: String::new(),
+        })
+    }
+
+    // Reopens a journal for appending, returning the blocks it records.
+    pub fn resume(path: &Path, header: &str) -> Result<(Self, WrittenBlocks), Error> {
+        let mut done = WrittenBlocks::new();
+        let file = File::open(path).map_err(Error::io(path))?;
+        let mut lines = BufReader::new(file).lines();
+        let found = lines
+            .next()
+            .transpose()
+            .map_err(Error::io(path))?
+            .unwrap_or_default();
+        if found != header {
+            return Err(Error::JournalMismatch {
+                path: path.to_owned(),
+                found,
+            });
+        }
+        for line in lines {
+            // A torn last line from a crash simply does not parse.
+            let line = line.map_err(Error::io(path))?;
+            if let Some((offset, checksum)) = line.split_once(' ') {
+                if let Ok(offset) = offset.parse() {
+                    done.insert((offset, checksum.to_owned()));
+                }
+            }
+        }
+        let mut file = OpenOptions::new()
+            .append(true)
+            .open(path)
+            .map_err(Error::io(path))?;
+        // Make sure new entries start on a line of their own.
+        writeln!(file).map_err(Error::io(path))?;
+        let journal = Self {
+            path: path.to_owned(),
+            file,
+            pending: String::new(),
+        };
+        Ok((journal, done))
+    }
+
+    pub fn record(&mut self, offset: u64, checksum: &str) {
+        self.pending += &format!("{} {}\n", offset, checksum);
+    }
+
+    // The caller must have synced the destination first.
+    pub fn commit(&mut self) -> Result<(), Error> {
+        if self.pending.is_empty() {
+            return Ok(());
+        }
+        self.file
+            .write_all(self.pending.as_bytes())
+            .and_then(|()| self.file.sync_data())
+            .map_err(Error::io(&self.path))?;
+        self.pending.clear();
+        Ok(())
+    }
+
+    pub fn remove(self) -> Result<(), Error> {
+        std::fs::remove_file(&self.path).map_err(Error::io(&self.path))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const HEADER: &str = "get-longhorn-backup backupstore/backup_b.cfg 8388608";
+    const A: &str = "0e40a436f8fb0e52220bfc58105122a14bb42e90ddc03e6366334c6d9e8ed50f";
+    const B: &str = "07845f9f6994e96c314a48a6c67740a65d91826a9bd39d869412fcc84117bab4";
+
+    fn journal_path(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!(
+            "get-longhorn-backup-{}-{}.journal",
+            std::process::id(),
+            name
+        ))
+    }
+
+    #[test]
+    fn resumes_committed_blocks() {
+        let path = journal_path("resume");
+        let mut journal = Journal::create(&path, HEADER).unwrap();
+        journal.record(0, A);
+        journal.commit().unwrap();
+        // Recorded but never committed, as when a restore is killed.
+        journal.record(4194304, B);
+        drop(journal);
+
+        let (mut journal, done) = Journal::resume(&path, HEADER).unwrap();
+        assert_eq!(done, WrittenBlocks::from([(0, A.to_owned())]));
+        journal.record(4194304, B);
+        journal.commit().unwrap();
+        let (journal, done) = Journal::resume(&path, HEADER).unwrap();
+        assert_eq!(
+            done,
+            WrittenBlocks::from([(0, A.to_owned()), (4194304, B.to_owned())])
+        );
+        journal.remove().unwrap();
+    }
+
+    #[test]
+    fn rejects_other_backup() {
+        let path = journal_path("mismatch");
+        Journal::create(&path, HEADER).unwrap();
+        let e = Journal::resume(&path, "get-longhorn-backup other.cfg 1").err();
+        std::fs::remove_file(&path).unwrap();
+        match e {
+            Some(Error::JournalMismatch { found, .. }) => assert_eq!(found, HEADER),
+            e => panic!("expected JournalMismatch, got {:?}", e),
+        }
+    }
+
+    #[test]
+    fn ignores_torn_last_line() {
+        let path = journal_path("torn");
+        std::fs::write(&path, format!("{}\n0 {}\n41943", HEADER, A)).unwrap();
+        let (mut journal, done) = Journal::resume(&path, HEADER).unwrap();
+        assert_eq!(done, WrittenBlocks::from([(0, A.to_owned())]));
+        // New entries must not run into the torn line.
+        journal.record(4194304, B);
+        journal.commit().unwrap();
+        let (journal, done) = Journal::resume(&path, HEADER).unwrap();
+        assert!(done.contains(&(4194304, B.to_owned())));
+        journal.remove().unwrap();
+    }
+}
@@ -0,0 +1,296 @@
+//! Reading Longhorn backups straight from their backup target: the
+//! backupstore layout, cfg models, a concurrent block fetcher, and
+//! restoring into files or block devices with a resumable journal, or
+//! verifying, checking and comparing local copies against backups
+//! without writing anything.
+
+use futures_core::stream::Stream;
+use futures_util::StreamExt;
+
+pub mod backupstore;
+pub mod check;
+pub mod checksum;
+pub mod compression;
+pub mod config;
+pub mod diff;
+pub mod error;
+pub mod journal;
+pub mod restore;
+pub mod secret;
+pub mod target;
+pub mod verify;
+pub mod writer;
+
+#[cfg(test)]
+mod fixture;
+
+// So callers can build the Bucket for an S3Target without depending on a
+// matching rust-s3 version themselves.
+pub use s3;
+
+use checksum::{longhorn_checksum, ChecksumMismatch, OnMismatch};
+use compression::CompressionMethod;
+use config::{BackupBlock, BackupCfg, VolumeCfg};
+use error::{Error, SkippedData};
+use target::BackupTarget;
+use verify::BadBlock;
+
+// Longhorn's DEFAULT_BLOCK_SIZE: every entry in Blocks covers this much
+// of the volume, and blocks that are entirely zero are left out.
+pub const BLOCK_SIZE: u64 = 2 << 20;
+
+// Each checksum goes into an object key, so it must be exactly what
+// Longhorn writes: 64 lowercase hex digits.
+pub fn check_checksum(block: &BackupBlock) -> Result<(), Error> {
+    let checksum = &block.BlockChecksum;
+    if checksum.len() != 64
+        || !checksum
+            .bytes()
+            .all(|b| matches!(b, b'0'..=b'9' | b'a'..=b'f'))
+    {
+        return Err(Error::InvalidChecksum {
+            offset: block.Offset,
+            checksum: checksum.clone(),
+        });
+    }
+    Ok(())
+}
+
+// Blocks must be in ascending order on BLOCK_SIZE boundaries within the
+// volume, with valid checksums. Gaps of whole blocks are sparse regions
+// and are fine; anything else means the index does not describe the
+// volume.
+pub fn check_blocks(blocks: &[BackupBlock], size: u64) -> Result<(), Error> {
+    let mut expected = 0;
+    for block in blocks {
+        check_checksum(block)?;
+        if block.Offset < expected
+            || (block.Offset - expected) % BLOCK_SIZE != 0
+            || block.Offset >= size
+        {
+            return Err(SkippedData {
+                expected_offset: expected,
+                found_offset: block.Offset,
+            }
+            .into());
+        }
+        expected = block.Offset + BLOCK_SIZE;
+    }
+    Ok(())
+}
+
+// The (offset, length) regions of the volume that have no block.
+pub fn sparse_regions(blocks: &[BackupBlock], size: u64) -> Vec<(u64, u64)> {
+    let mut regions = Vec::new();
+    let mut next = 0;
+    for block in blocks {
+        if block.Offset > next {
+            regions.push((next, block.Offset - next));
+        }
+        next = block.Offset + BLOCK_SIZE;
+    }
+    if size > next {
+        regions.push((next, size - next));
+    }
+    regions
+}
+
+// Hears about a restore or verification as it goes, e.g. to show
+// progress. Every method does nothing by default.
+pub trait Observer {
+    // How many blocks there are to go through.
+    fn start(&mut self, _blocks: usize) {}
+
+    // One block is done with: stored_len bytes fetched, len after
+    // decompression.
+    fn block(&mut self, _stored_len: usize, _len: usize) {}
+
+    // A block that did not match its checksum was written anyway, with
+    // OnMismatch::Report.
+    fn mismatch(&mut self, _mismatch: &ChecksumMismatch) {}
+
+    // verify found a block that cannot be restored.
+    fn bad_block(&mut self, _bad: &BadBlock) {}
+}
+
+impl Observer for () {}
+
+pub struct FetchedBlock<'a> {
+    pub block: &'a BackupBlock,
+    pub data: Vec<u8>,
+    // Size of the object as stored, before decompression.
+    pub stored_len: usize,
+    // Only with OnMismatch::Report; otherwise a mismatch is an error.
+    pub mismatch: Option<ChecksumMismatch>,
+}
+
+// Fetches, decompresses and checks one block of the volume in volume_dir.
+pub async fn fetch_block<'a>(
+    target: &dyn BackupTarget,
+    volume_dir: &str,
+    block: &'a BackupBlock,
+    compression: CompressionMethod,
+    on_mismatch: OnMismatch,
+) -> Result<FetchedBlock<'a>, Error> {
+    check_checksum(block)?;
+    let blockname = backupstore::block_key(volume_dir, &block.BlockChecksum);
+    let contents = target.get(&blockname).await?;
+    let stored_len = contents.len();
+    let (data, found) = tokio::task::spawn_blocking(move || {
+        let data = compression.decompress(&contents)?;
+        let found = longhorn_checksum(&data);
+        Ok::<_, std::io::Error>((data, found))
+    })
+    .await?
+    .map_err(|source| Error::Decode {
+        offset: block.Offset,
+        checksum: block.BlockChecksum.clone(),
+        source,
+    })?;
+    let mut mismatch = None;
+    if found != block.BlockChecksum {
+        let m = ChecksumMismatch {
+            offset: block.Offset,
+            expected: block.BlockChecksum.clone(),
+            found,
+        };
+        match on_mismatch {
+            OnMismatch::Fail => return Err(m.into()),
+            OnMismatch::Report => mismatch = Some(m),
+        }
+    }
+    Ok(FetchedBlock {
+        block,
+        data,
+        stored_len,
+        mismatch,
+    })
+}
+
+pub fn get_backup<'a>(
+    target: &'a dyn BackupTarget,
+    volume_dir: &'a str,
+    blocks: impl IntoIterator<Item = &'a BackupBlock> + 'a,
+    compression: CompressionMethod,
+    on_mismatch: OnMismatch,
+    jobs: usize,
+) -> impl Stream<Item = Result<FetchedBlock<'a>, Error>> + 'a {
+    futures_util::stream::iter(blocks)
+        .map(move |block| fetch_block(target, volume_dir, block, compression, on_mismatch))
+        .buffer_unordered(jobs)
+}
+
+pub async fn load_backup_cfg(target: &dyn BackupTarget, key: &str) -> Result<BackupCfg, Error> {
+    let index = target.get(key).await?;
+    serde_json::from_slice::<BackupCfg>(&index).map_err(|source| Error::Config {
+        key: key.to_owned(),
+        source,
+    })
+}
+
+pub async fn load_volume_cfg(
+    target: &dyn BackupTarget,
+    volume_dir: &str,
+) -> Result<VolumeCfg, Error> {
+    let key = format!("{}volume.cfg", volume_dir);
+    let cfg = target.get(&key).await?;
+    serde_json::from_slice::<VolumeCfg>(&cfg).map_err(|source| Error::Config { key, source })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MIB: u64 = 1 << 20;
+
+    fn block(offset: u64) -> BackupBlock {
+        BackupBlock {
+            Offset: offset,
+            BlockChecksum: longhorn_checksum(&offset.to_le_bytes()),
+        }
+    }
+
+    fn skipped(result: Result<(), Error>) -> (u64, u64) {
+        match result {
+            Err(Error::SkippedData(e)) => (e.expected_offset, e.found_offset),
+            r => panic!("expected SkippedData, got {:?}", r),
+        }
+    }
+
+    #[test]
+    fn accepts_whole_block_gaps() {
+        let blocks = [block(0), block(4 * MIB), block(8 * MIB)];
+        check_blocks(&blocks, 12 * MIB).unwrap();
+        check_blocks(&[], 12 * MIB).unwrap();
+    }
+
+    #[test]
+    fn rejects_misaligned_gap() {
+        let blocks = [block(0), block(3 * MIB)];
+        assert_eq!(skipped(check_blocks(&blocks, 8 * MIB)), (2 * MIB, 3 * MIB));
+    }
+
+    #[test]
+    fn rejects_out_of_order() {
+        let blocks = [block(4 * MIB), block(0)];
+        assert_eq!(skipped(check_blocks(&blocks, 8 * MIB)), (6 * MIB, 0));
+    }
+
+    #[test]
+    fn rejects_duplicate() {
+        let blocks = [block(0), block(2 * MIB), block(2 * MIB)];
+        assert_eq!(skipped(check_blocks(&blocks, 8 * MIB)), (4 * MIB, 2 * MIB));
+    }
+
+    #[test]
+    fn rejects_offset_past_size() {
+        let blocks = [block(0), block(4 * MIB)];
+        assert_eq!(skipped(check_blocks(&blocks, 4 * MIB)), (2 * MIB, 4 * MIB));
+    }
+
+    #[test]
+    fn rejects_invalid_checksums() {
+        let valid = block(0).BlockChecksum;
+        for checksum in ["ab".to_owned(), "../..".to_owned(), valid.to_uppercase()] {
+            let blocks = [BackupBlock {
+                Offset: 0,
+                BlockChecksum: checksum,
+            }];
+            assert!(matches!(
+                check_blocks(&blocks, 2 * MIB),
+                Err(Error::InvalidChecksum { offset: 0, .. })
+            ));
+        }
+    }
+
+    #[tokio::test]
+    async fn fetch_rejects_invalid_checksum() {
+        let target = target::FsTarget::new(std::env::temp_dir());
+        for checksum in ["", "a", "é"] {
+            let block = BackupBlock {
+                Offset: 0,
+                BlockChecksum: checksum.to_owned(),
+            };
+            let fetched = fetch_block(
+                &target,
+                "",
+                &block,
+                CompressionMethod::None,
+                OnMismatch::Fail,
+            )
+            .await;
+            assert!(matches!(fetched, Err(Error::InvalidChecksum { .. })));
+        }
+    }
+
+    #[test]
+    fn finds_sparse_regions() {
+        let blocks = [block(2 * MIB), block(4 * MIB), block(10 * MIB)];
+        assert_eq!(
+            sparse_regions(&blocks, 16 * MIB),
+            [(0, 2 * MIB), (6 * MIB, 4 * MIB), (12 * MIB, 4 * MIB)]
+        );
+        assert_eq!(sparse_regions(&[], 4 * MIB), [(0, 4 * MIB)]);
+        assert_eq!(sparse_regions(&[block(0)], 2 * MIB), []);
+    }
+}
@@ -1,109 +1,838 @@
-use async_stream::stream;
-use futures_core::stream::Stream;
-use futures_util::{pin_mut, StreamExt};
-use lz4::Decoder;
+use clap::{Args, Parser, Subcommand};
+use get_longhorn_backup::check;
+use get_longhorn_backup::checksum::{ChecksumMismatch, OnMismatch};
+use get_longhorn_backup::diff;
+use get_longhorn_backup::error::{error_chain, Error, ErrorKind};
+use get_longhorn_backup::restore::{self, RestoreOptions};
+use get_longhorn_backup::secret::BackupTargetSecret;
+use get_longhorn_backup::target::{
+    Addressing, AzureTarget, BackupTarget, FsTarget, RetryPolicy, S3Target, TargetUrl, TlsOptions,
+};
+use get_longhorn_backup::verify::{self, BadBlock};
+use get_longhorn_backup::writer::GapFill;
+use get_longhorn_backup::{
+    backupstore, check_blocks, load_backup_cfg, load_volume_cfg, Observer, BLOCK_SIZE,
+};
 use s3::creds::Credentials;
 use s3::Bucket;
 use s3::Region;
-use serde::Deserialize;
-use std::fs::File;
-use std::io::{Read, Seek, Write};
+use std::num::{NonZeroU32, NonZeroUsize};
 use std::path::PathBuf;
+use std::process::ExitCode;
+use std::sync::Arc;
+use std::time::Duration;
 
-#[derive(Deserialize, Debug)]
-#[allow(non_snake_case)]
-struct BackupBlock {
-    Offset: u64,
-    BlockChecksum: String,
+mod output;
+mod progress;
+
+use output::OutputFormat;
+use progress::{Progress, ProgressMode};
+
+const DEFAULT_JOBS: NonZeroUsize = NonZeroUsize::new(8).unwrap();
+
+#[derive(Parser)]
+#[command(
+    version,
+    about = "Restore Longhorn volume backups from an S3, Azure Blob or directory backup target",
+    after_help = output::EXIT_CODES_HELP
+)]
+struct Cli {
+    #[command(flatten)]
+    target: TargetArgs,
+    /// Output format; json prints a machine-readable result or error on
+    /// stdout
+    #[arg(long, global = true, default_value = "text")]
+    output: OutputFormat,
+    #[command(subcommand)]
+    command: Command,
 }
 
-#[derive(Deserialize, Debug)]
-#[allow(non_snake_case)]
-struct BackupCfg {
-    CompressionMethod: String,
-    Blocks: Vec<BackupBlock>,
+#[derive(Args)]
+struct TargetArgs {
+    /// S3 endpoint URL; defaults to the one in --secret, or AWS itself
+    #[arg(long, env = "AWS_ENDPOINTS")]
+    endpoint: Option<String>,
+    /// Longhorn backup target URL, which overrides --bucket, --region and
+    /// --prefix: s3://bucket@region/path/,
+    /// azblob://container@core.windows.net/path/, or file:///path (or
+    /// just /path) for a local directory or a mounted NFS target
+    #[arg(long, env = "BACKUP_TARGET")]
+    target: Option<TargetUrl>,
+    /// S3 region
+    #[arg(long, env = "AWS_REGION", required_unless_present = "target")]
+    region: Option<String>,
+    /// Bucket holding the backupstore
+    #[arg(long, env = "BACKUP_BUCKET", required_unless_present = "target")]
+    bucket: Option<String>,
+    /// Path within the bucket that contains the backupstore directory
+    #[arg(long, env = "BACKUP_PREFIX")]
+    prefix: Option<String>,
+    /// Access key ID; without one, credentials come from --secret, the
+    /// AWS_* environment variables (including web identity role
+    /// assumption), ~/.aws/credentials or instance metadata
+    #[arg(long, requires = "secret_key")]
+    access_key: Option<String>,
+    /// Secret access key to go with --access-key
+    #[arg(long, requires = "access_key")]
+    secret_key: Option<String>,
+    /// Session token to go with --access-key for temporary credentials;
+    /// with --secret it comes from AWS_SESSION_TOKEN there
+    #[arg(long, requires = "access_key")]
+    session_token: Option<String>,
+    /// Profile in ~/.aws/credentials to use when neither --access-key nor
+    /// the secret gives keys
+    #[arg(long, env = "AWS_PROFILE")]
+    profile: Option<String>,
+    /// Azure storage account for azblob:// targets
+    #[arg(long, env = "AZBLOB_ACCOUNT_NAME")]
+    azblob_account: Option<String>,
+    /// Access key of the Azure storage account; without one, requests
+    /// are anonymous
+    #[arg(long, env = "AZBLOB_ACCOUNT_KEY")]
+    azblob_key: Option<String>,
+    /// Blob service endpoint in place of https://<account>.blob.<suffix>,
+    /// e.g. for Azurite
+    #[arg(long, env = "AZBLOB_ENDPOINT")]
+    azblob_endpoint: Option<String>,
+    /// Longhorn backup target secret: a mounted secret directory, or the
+    /// output of `kubectl get secret -o json` (- for stdin)
+    #[arg(long, value_name = "PATH")]
+    secret: Option<PathBuf>,
+    /// How to put the bucket into S3 URLs: path (endpoint/bucket), virtual
+    /// (bucket.endpoint), or auto, which like Longhorn uses path style for
+    /// endpoints other than AWS unless VIRTUAL_HOSTED_STYLE is true
+    #[arg(long, default_value = "auto")]
+    addressing: Addressing,
+    /// PEM file of CA certificates to trust besides the system ones
+    /// [default: the PEM in AWS_CERT for S3 or AZBLOB_CERT for Azure, from
+    /// --secret or else the environment, as Longhorn uses them]
+    #[arg(long, value_name = "PATH")]
+    ca_cert: Option<PathBuf>,
+    /// PEM certificate to present to endpoints that require client
+    /// certificates
+    #[arg(long, value_name = "PATH", requires = "client_key")]
+    client_cert: Option<PathBuf>,
+    /// PKCS#8 PEM private key of --client-cert
+    #[arg(long, value_name = "PATH", requires = "client_cert")]
+    client_key: Option<PathBuf>,
+    /// Do not verify the endpoint's certificate or hostname. Only for test
+    /// setups
+    #[arg(long)]
+    insecure_skip_tls_verify: bool,
+    /// How many times to try each request before giving up
+    #[arg(long, default_value = "5")]
+    retries: NonZeroU32,
+    /// Delay before the first retry, doubling on each further attempt
+    #[arg(long, default_value_t = 200)]
+    retry_delay_ms: u64,
+    /// Upper bound on the delay between retries
+    #[arg(long, default_value_t = 20_000)]
+    retry_max_delay_ms: u64,
 }
 
-#[derive(Debug)]
-struct SkippedData {
-    expected_offset: usize,
-    found_offset: usize,
+#[derive(Args)]
+struct BackupArgs {
+    /// Object key of the backup_*.cfg
+    #[arg(
+        long,
+        conflicts_with_all = ["volume", "backup", "latest"],
+        required_unless_present = "volume"
+    )]
+    backup_cfg: Option<String>,
+    /// Name of the volume the backup belongs to
+    #[arg(long)]
+    volume: Option<String>,
+    /// Name of the backup within the volume, e.g. backup-0123456789abcdef
+    #[arg(
+        long,
+        requires = "volume",
+        conflicts_with = "latest",
+        required_unless_present_any = ["backup_cfg", "latest"]
+    )]
+    backup: Option<String>,
+    /// Use the most recent backup of the volume
+    #[arg(long, requires = "volume")]
+    latest: bool,
 }
 
-impl std::fmt::Display for SkippedData {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(
-            f,
-            "Gap in data: expected to find a block for offset {}, found {}",
-            self.expected_offset, self.found_offset
-        )
+impl BackupArgs {
+    // The volume directory (with trailing slash) and the backup cfg key.
+    async fn locate(
+        &self,
+        target: &dyn BackupTarget,
+        root: &str,
+    ) -> Result<(String, String), Error> {
+        if let Some(backup_cfg) = &self.backup_cfg {
+            return Ok((volume_dir_of(backup_cfg)?.to_owned(), backup_cfg.clone()));
+        }
+        let volume = self.volume.as_deref().unwrap();
+        let volume_dir = backupstore::volume_dir(root, volume);
+        let backup = match &self.backup {
+            Some(backup) => backup.clone(),
+            None => {
+                let volume_cfg = load_volume_cfg(target, &volume_dir).await?;
+                if volume_cfg.LastBackupName.is_empty() {
+                    return Err(Error::NotFound(format!("Volume {} has no backups", volume)));
+                }
+                volume_cfg.LastBackupName
+            }
+        };
+        let backup_cfg = backupstore::backup_cfg_key(&volume_dir, &backup);
+        Ok((volume_dir, backup_cfg))
     }
 }
 
-impl std::error::Error for SkippedData {}
+// A Longhorn setting from the environment, where like in the secret an
+// empty value means it is not set.
+fn env_setting(name: &str) -> Option<String> {
+    std::env::var(name)
+        .ok()
+        .map(|v| v.trim().to_owned())
+        .filter(|v| !v.is_empty())
+}
 
-fn get_backup<'a>(
-    bucket: &'a Bucket,
-    basename: &'a str,
-    blocks: &'a [BackupBlock],
-) -> impl Stream<Item = Result<(u64, Vec<u8>), Box<dyn std::error::Error>>> + 'a {
-    stream! {
-        for block in blocks {
-            let blockname = format!("{}/blocks/{}/{}/{}.blk", basename, &block.BlockChecksum[0..2], &block.BlockChecksum[2..4], &block.BlockChecksum);
-            let contents = bucket.get_object(blockname).await?;
-            let mut dec = Decoder::new(contents.as_slice())?;
-            let mut out = Vec::new();
-            dec.read_to_end(&mut out)?;
-            yield Ok((block.Offset, out));
+// The volume directory a backup_*.cfg key is in, with trailing slash.
+fn volume_dir_of(backup_cfg: &str) -> Result<&str, Error> {
+    match backup_cfg.rmatch_indices('/').nth(1) {
+        Some((base_i, _)) => Ok(&backup_cfg[..=base_i]),
+        None => Err(Error::Usage(
+            "backup name must have at least 2 slashes so we can find the backup root.".to_owned(),
+        )),
+    }
+}
+
+#[derive(Args)]
+struct RestoreArgs {
+    #[command(flatten)]
+    backup: BackupArgs,
+    /// Number of blocks to fetch and decompress concurrently
+    #[arg(long, default_value_t = DEFAULT_JOBS)]
+    jobs: NonZeroUsize,
+    /// Report blocks that do not match their checksum instead of failing
+    #[arg(long)]
+    report_checksum_mismatch: bool,
+    /// Punch holes for all-zero blocks instead of writing them
+    #[arg(long)]
+    punch_zeroes: bool,
+    /// Restore onto an existing block device rather than a file
+    #[arg(long)]
+    block_device: bool,
+    /// On a block device, what to do with regions the backup has no data
+    /// for
+    #[arg(long, default_value = "leave", requires = "block_device")]
+    gaps: GapFill,
+    /// Continue an interrupted restore, skipping blocks its journal
+    /// records as written
+    #[arg(long)]
+    resume: bool,
+    /// Journal of written blocks [default: <DST>.journal, none for a
+    /// block device]
+    #[arg(long)]
+    journal: Option<PathBuf>,
+    /// How to show progress; auto draws a bar on a terminal and prints a
+    /// line every few seconds otherwise
+    #[arg(long, default_value = "auto")]
+    progress: ProgressMode,
+    /// Where to write the restored volume
+    dst: PathBuf,
+}
+
+#[derive(Args)]
+struct VerifyArgs {
+    #[command(flatten)]
+    backup: BackupArgs,
+    /// Number of blocks to fetch and decompress concurrently
+    #[arg(long, default_value_t = DEFAULT_JOBS)]
+    jobs: NonZeroUsize,
+    /// How to show progress
+    #[arg(long, default_value = "auto")]
+    progress: ProgressMode,
+}
+
+#[derive(Args)]
+struct CheckArgs {
+    /// Object key of a backup_*.cfg to check
+    #[arg(long, conflicts_with = "volume")]
+    backup_cfg: Option<String>,
+    /// Check the backups of this volume only
+    #[arg(long)]
+    volume: Option<String>,
+    /// Check only this backup of --volume
+    #[arg(long, requires = "volume")]
+    backup: Option<String>,
+    /// Number of requests to run concurrently
+    #[arg(long, default_value_t = DEFAULT_JOBS)]
+    jobs: NonZeroUsize,
+}
+
+impl CheckArgs {
+    // The volume directory and cfg key of every backup to check.
+    async fn backups(
+        &self,
+        target: &dyn BackupTarget,
+        root: &str,
+    ) -> Result<Vec<(String, String)>, Error> {
+        if let Some(backup_cfg) = &self.backup_cfg {
+            return Ok(vec![(
+                volume_dir_of(backup_cfg)?.to_owned(),
+                backup_cfg.clone(),
+            )]);
+        }
+        let volume_dirs = match &self.volume {
+            Some(volume) => vec![backupstore::volume_dir(root, volume)],
+            None => backupstore::volume_dirs(target, root).await?,
+        };
+        let mut backups = Vec::new();
+        for volume_dir in volume_dirs {
+            let keys = match &self.backup {
+                Some(backup) => vec![backupstore::backup_cfg_key(&volume_dir, backup)],
+                None => backupstore::backup_cfg_keys(target, &volume_dir).await?,
+            };
+            backups.extend(keys.into_iter().map(|key| (volume_dir.clone(), key)));
+        }
+        if backups.is_empty() {
+            if let Some(volume) = &self.volume {
+                return Err(Error::NotFound(format!("Volume {} has no backups", volume)));
+            }
         }
+        Ok(backups)
     }
 }
 
-#[tokio::main]
-async fn main() -> Result<(), Box<dyn std::error::Error>> {
-    let args: Vec<_> = std::env::args_os().collect();
-    if args.len() != 6 {
-        eprintln!(
-            "Usage: {} endpoint region bucket backup-cfg-name dst",
-            args[0].to_string_lossy()
-        );
-        std::process::exit(3);
-    }
-    let s3_endpoint = args[1].to_string_lossy().into_owned();
-    let s3_region_name = args[2].to_string_lossy().into_owned();
-    let bucket_name = args[3].to_string_lossy().into_owned();
-    let backup_name = args[4].to_string_lossy().into_owned();
-    let dst_path = PathBuf::from(args[5].clone());
-
-    let basename = if let Some((base_i, _)) = backup_name.rmatch_indices('/').nth(1) {
-        &backup_name[..base_i]
-    } else {
-        eprintln!("backup name must have at least 2 slashes so we can find the backup root.");
-        std::process::exit(1);
-    };
+#[derive(Args)]
+struct DiffArgs {
+    #[command(flatten)]
+    backup: BackupArgs,
+    /// Number of blocks to read and hash concurrently
+    #[arg(long, default_value_t = DEFAULT_JOBS)]
+    jobs: NonZeroUsize,
+    /// File or block device to compare, such as a restored image or the
+    /// volume itself
+    path: PathBuf,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Restore a backup into a file or onto a block device
+    Restore(RestoreArgs),
+    /// Check that every block of a backup can be fetched, decompressed and
+    /// matches its checksum, without writing anything
+    Verify(VerifyArgs),
+    /// Check that the blocks of one or more backups exist and have
+    /// plausible sizes, from listings of the blocks directories and
+    /// without downloading any block. Checks every backup in the
+    /// backupstore unless told otherwise
+    Check(CheckArgs),
+    /// Compare a local file or block device against a backup's block
+    /// checksums, without downloading any block
+    Diff(DiffArgs),
+    /// Show what a backup contains
+    Inspect {
+        #[command(flatten)]
+        backup: BackupArgs,
+    },
+    /// List the volumes and backups in the backupstore
+    List,
+}
+
+impl TargetArgs {
+    fn open(&self) -> Result<Box<dyn BackupTarget>, Error> {
+        // The target URL names the region the way Longhorn uses it, so it
+        // wins over AWS_REGION.
+        let (bucket_name, region) = match &self.target {
+            Some(TargetUrl::Dir(path)) if !path.is_dir() => {
+                return Err(Error::NotFound(format!(
+                    "Backup target {} is not a directory",
+                    path.display()
+                )))
+            }
+            Some(TargetUrl::Dir(path)) => return Ok(Box::new(FsTarget::new(path))),
+            Some(TargetUrl::Azure {
+                container,
+                endpoint_suffix,
+                ..
+            }) => return self.open_azure(container, endpoint_suffix),
+            Some(TargetUrl::S3 { bucket, region, .. }) => (bucket, region),
+            None => (self.bucket.as_ref().unwrap(), self.region.as_ref().unwrap()),
+        };
+        let secret = self.secret()?;
+        let client = self.tls(&secret, "AWS_CERT")?.client()?;
+        let s3_cred = self.credentials(&secret)?;
+        let s3_region = match self.endpoint.as_deref().or(secret.get("AWS_ENDPOINTS")) {
+            Some(endpoint) => Region::Custom {
+                region: region.clone(),
+                endpoint: endpoint.to_owned(),
+            },
+            None => match region.parse().unwrap() {
+                Region::Custom { .. } => {
+                    return Err(Error::Usage(format!(
+                        "{} is not an AWS region; pass --endpoint for other S3 services",
+                        region
+                    )))
+                }
+                region => region,
+            },
+        };
+        let virtual_hosted_style = secret
+            .get("VIRTUAL_HOSTED_STYLE")
+            .map(str::to_owned)
+            .or_else(|| env_setting("VIRTUAL_HOSTED_STYLE"))
+            .is_some_and(|v| v.eq_ignore_ascii_case("true"));
+        let path_style = self.addressing.path_style(&s3_region, virtual_hosted_style);
+        let mut bucket =
+            Bucket::new(bucket_name, s3_region, s3_cred).map_err(|source| Error::S3 {
+                key: bucket_name.clone(),
+                source,
+            })?;
+        if path_style {
+            bucket.set_path_style();
+        }
+        Ok(Box::new(S3Target::new(bucket, client, self.retry_policy())))
+    }
 
-    let s3_cred = Credentials::default().unwrap();
-    let s3_region = Region::Custom {
-        region: s3_region_name,
-        endpoint: s3_endpoint,
+    fn open_azure(
+        &self,
+        container: &str,
+        endpoint_suffix: &str,
+    ) -> Result<Box<dyn BackupTarget>, Error> {
+        let secret = self.secret()?;
+        let Some(account) = self
+            .azblob_account
+            .as_deref()
+            .or(secret.get("AZBLOB_ACCOUNT_NAME"))
+        else {
+            return Err(Error::Usage(
+                "azblob:// targets need --azblob-account or AZBLOB_ACCOUNT_NAME".to_owned(),
+            ));
+        };
+        let target = AzureTarget::new(
+            self.tls(&secret, "AZBLOB_CERT")?.client()?,
+            account,
+            self.azblob_key
+                .as_deref()
+                .or(secret.get("AZBLOB_ACCOUNT_KEY")),
+            self.azblob_endpoint
+                .as_deref()
+                .or(secret.get("AZBLOB_ENDPOINT")),
+            endpoint_suffix,
+            container,
+            self.retry_policy(),
+        )?;
+        Ok(Box::new(target))
+    }
+
+    // cert_setting is where Longhorn takes the CA certificate from for
+    // this kind of target.
+    fn tls(&self, secret: &BackupTargetSecret, cert_setting: &str) -> Result<TlsOptions, Error> {
+        let ca_certs = match &self.ca_cert {
+            Some(path) => Some(std::fs::read(path).map_err(Error::io(path))?),
+            None => secret
+                .get(cert_setting)
+                .map(str::to_owned)
+                .or_else(|| env_setting(cert_setting))
+                .map(String::into_bytes),
+        };
+        let client_identity = match (&self.client_cert, &self.client_key) {
+            (Some(cert), Some(key)) => Some((
+                std::fs::read(cert).map_err(Error::io(cert))?,
+                std::fs::read(key).map_err(Error::io(key))?,
+            )),
+            _ => None,
+        };
+        Ok(TlsOptions {
+            ca_certs,
+            client_identity,
+            insecure: self.insecure_skip_tls_verify,
+        })
+    }
+
+    fn secret(&self) -> Result<BackupTargetSecret, Error> {
+        match &self.secret {
+            Some(path) => BackupTargetSecret::load(path),
+            None => Ok(BackupTargetSecret::default()),
+        }
+    }
+
+    fn retry_policy(&self) -> RetryPolicy {
+        RetryPolicy {
+            attempts: self.retries.get(),
+            base_delay: Duration::from_millis(self.retry_delay_ms),
+            max_delay: Duration::from_millis(self.retry_max_delay_ms),
+            on_retry: Some(Arc::new(|e| {
+                progress::eprintln(&format!(
+                    "{} failed (attempt {}/{}), retrying in {:?}: {}",
+                    e.what, e.attempt, e.attempts, e.delay, e.error
+                ))
+            })),
+        }
+    }
+
+    fn credentials(&self, secret: &BackupTargetSecret) -> Result<Credentials, Error> {
+        let (result, from) = if let Some(access_key) = &self.access_key {
+            let result = Credentials::new(
+                Some(access_key),
+                self.secret_key.as_deref(),
+                None,
+                self.session_token.as_deref(),
+                None,
+            );
+            (result, "--access-key and --secret-key".to_owned())
+        } else if let Some(access_key) = secret.get("AWS_ACCESS_KEY_ID") {
+            let Some(secret_key) = secret.get("AWS_SECRET_ACCESS_KEY") else {
+                return Err(Error::Usage(
+                    "The secret has AWS_ACCESS_KEY_ID but no AWS_SECRET_ACCESS_KEY".to_owned(),
+                ));
+            };
+            let result = Credentials::new(
+                Some(access_key),
+                Some(secret_key),
+                None,
+                secret.get("AWS_SESSION_TOKEN"),
+                None,
+            );
+            (result, "the secret".to_owned())
+        } else if let Some(profile) = &self.profile {
+            let result = Credentials::from_profile(Some(profile));
+            (result, format!("profile {} in ~/.aws/credentials", profile))
+        } else {
+            let from = "the environment, ~/.aws/credentials or instance metadata; \
+                        pass --access-key and --secret-key, --profile or --secret";
+            (Credentials::default(), from.to_owned())
+        };
+        result.map_err(|source| Error::Credentials { from, source })
+    }
+
+    // The prefix as a directory: empty, or ending in a slash.
+    fn root(&self) -> String {
+        let prefix = match &self.target {
+            Some(TargetUrl::S3 { prefix, .. } | TargetUrl::Azure { prefix, .. }) => prefix,
+            // The directory itself is the root.
+            Some(TargetUrl::Dir(_)) => "",
+            None => self.prefix.as_deref().unwrap_or_default(),
+        };
+        let prefix = prefix.trim_matches('/');
+        if prefix.is_empty() {
+            String::new()
+        } else {
+            format!("{}/", prefix)
+        }
+    }
+}
+
+// Shows progress and reports problems as they come up.
+struct Reporter {
+    mode: ProgressMode,
+    output: OutputFormat,
+    progress: Option<Progress>,
+}
+
+impl Reporter {
+    fn new(mode: ProgressMode, output: OutputFormat) -> Self {
+        Self {
+            mode,
+            output,
+            progress: None,
+        }
+    }
+
+    fn finish(&self) {
+        if let Some(progress) = &self.progress {
+            progress.finish();
+        }
+    }
+}
+
+impl Observer for Reporter {
+    fn start(&mut self, blocks: usize) {
+        self.progress = Some(Progress::new(self.mode, blocks as u64));
+    }
+
+    fn block(&mut self, stored_len: usize, len: usize) {
+        if let Some(progress) = &mut self.progress {
+            progress.block(stored_len, len);
+        }
+    }
+
+    fn mismatch(&mut self, mismatch: &ChecksumMismatch) {
+        if self.output == OutputFormat::Text {
+            progress::eprintln(&mismatch.to_string());
+        }
+    }
+
+    fn bad_block(&mut self, bad: &BadBlock) {
+        if self.output == OutputFormat::Text {
+            progress::eprintln(&format!(
+                "Block {} at offset {} is {}: {}",
+                bad.checksum, bad.offset, bad.problem, bad.detail
+            ));
+        }
+    }
+}
+
+async fn restore(
+    target: &dyn BackupTarget,
+    root: &str,
+    args: &RestoreArgs,
+    output: OutputFormat,
+) -> Result<(), Error> {
+    let (volume_dir, backup_cfg) = args.backup.locate(target, root).await?;
+    let options = RestoreOptions {
+        jobs: args.jobs.get(),
+        on_mismatch: if args.report_checksum_mismatch {
+            OnMismatch::Report
+        } else {
+            OnMismatch::Fail
+        },
+        punch_zeroes: args.punch_zeroes,
+        block_device: args.block_device,
+        gaps: args.gaps,
+        resume: args.resume,
+        journal: args.journal.clone(),
     };
+    let mut reporter = Reporter::new(args.progress, output);
+    let summary = restore::restore(
+        target,
+        &volume_dir,
+        &backup_cfg,
+        &args.dst,
+        &options,
+        &mut reporter,
+    )
+    .await?;
+    reporter.finish();
+    if output == OutputFormat::Json {
+        output::print_json(&summary);
+    }
+    Ok(())
+}
+
+async fn verify(
+    target: &dyn BackupTarget,
+    root: &str,
+    args: &VerifyArgs,
+    output: OutputFormat,
+) -> Result<ExitCode, Error> {
+    let (volume_dir, backup_cfg) = args.backup.locate(target, root).await?;
+    let mut reporter = Reporter::new(args.progress, output);
+    let summary = verify::verify(
+        target,
+        &volume_dir,
+        &backup_cfg,
+        args.jobs.get(),
+        &mut reporter,
+    )
+    .await?;
+    reporter.finish();
+    if output == OutputFormat::Text {
+        println!(
+            "Verified {}: {} of {} blocks OK, {} bad",
+            backup_cfg,
+            summary.blocks_ok,
+            summary.blocks,
+            summary.bad_blocks.len()
+        );
+    }
+    Ok(output::report_summary(output, &summary, summary.error()))
+}
+
+async fn check(
+    target: &dyn BackupTarget,
+    root: &str,
+    args: &CheckArgs,
+    output: OutputFormat,
+) -> Result<ExitCode, Error> {
+    let backups = args.backups(target, root).await?;
+    let summary = check::check(target, &backups, args.jobs.get()).await?;
+    if output == OutputFormat::Text {
+        for bad in &summary.bad_blocks {
+            match bad.size {
+                None => eprintln!("Block {} is missing", bad.key),
+                Some(size) => eprintln!("Block {} has an implausible size of {}", bad.key, size),
+            }
+            for backup in &bad.backups {
+                eprintln!("  used by {}", backup);
+            }
+        }
+        for bad in &summary.bad_backups {
+            eprintln!("Backup {} is {}: {}", bad.backup, bad.problem, bad.detail);
+        }
+        println!(
+            "Checked {} blocks of {} backups in {} listings, {} bad blocks, {} bad backups",
+            summary.blocks,
+            summary.backups,
+            summary.listings,
+            summary.bad_blocks.len(),
+            summary.bad_backups.len()
+        );
+    }
+    Ok(output::report_summary(output, &summary, summary.error()))
+}
 
-    let bucket = Bucket::new(&bucket_name, s3_region, s3_cred).unwrap();
+async fn diff(
+    target: &dyn BackupTarget,
+    root: &str,
+    args: &DiffArgs,
+    output: OutputFormat,
+) -> Result<ExitCode, Error> {
+    let (_, backup_cfg) = args.backup.locate(target, root).await?;
+    let summary = diff::diff(target, &backup_cfg, &args.path, args.jobs.get()).await?;
+    if output == OutputFormat::Text {
+        for block in &summary.different_blocks {
+            println!(
+                "Block at offset {} differs: expected {}, found {}",
+                block.offset, block.expected, block.found
+            );
+        }
+        for region in &summary.extra_data {
+            println!(
+                "Data where the backup has none at offset {}, {} bytes",
+                region.offset, region.length
+            );
+        }
+        println!(
+            "{} against {}: {} of {} blocks match, {} differ, {} bytes of data where the backup has none",
+            args.path.display(),
+            backup_cfg,
+            summary.blocks_matching,
+            summary.blocks,
+            summary.different_blocks.len(),
+            summary.extra_bytes()
+        );
+    }
+    Ok(output::report_summary(output, &summary, summary.error()))
+}
 
-    let index = bucket.get_object(&backup_name).await?;
-    let index = serde_json::from_slice::<BackupCfg>(index.as_slice())?;
+async fn inspect(
+    target: &dyn BackupTarget,
+    root: &str,
+    backup: &BackupArgs,
+    output: OutputFormat,
+) -> Result<(), Error> {
+    let (_, backup_cfg) = backup.locate(target, root).await?;
+    let index = load_backup_cfg(target, &backup_cfg).await?;
+    if output == OutputFormat::Json {
+        output::print_json(&serde_json::json!({
+            "backup": index,
+            "index_error": check_blocks(&index.Blocks, index.Size).err().map(|e| e.to_string()),
+        }));
+        return Ok(());
+    }
+    println!("Name: {}", index.Name);
+    println!("VolumeName: {}", index.VolumeName);
+    println!("SnapshotName: {}", index.SnapshotName);
+    println!("SnapshotCreatedAt: {}", index.SnapshotCreatedAt);
+    println!("CreatedTime: {}", index.CreatedTime);
+    println!("Size: {}", index.Size);
+    println!("IsIncremental: {}", index.IsIncremental);
+    println!("CompressionMethod: {}", index.CompressionMethod);
+    for (k, v) in index.Labels.iter().flatten() {
+        println!("Label: {}={}", k, v);
+    }
+    for (k, v) in &index.extra {
+        println!("{}: {}", k, v);
+    }
+    println!(
+        "Blocks: {} ({} bytes of data)",
+        index.Blocks.len(),
+        index.Blocks.len() as u64 * BLOCK_SIZE
+    );
+    match check_blocks(&index.Blocks, index.Size) {
+        Ok(()) => println!("Index: consistent"),
+        Err(e) => println!("Index: {}", e),
+    }
+    Ok(())
+}
 
-    if index.CompressionMethod != "lz4" {
-        eprintln!("Only support lz4 as a CompressionMethod");
-        std::process::exit(1);
+// Whether an error loading one cfg in a listing is down to that entry,
+// such as a volume part way through deletion, rather than to the target.
+fn entry_error(e: Error) -> Result<String, Error> {
+    match e.kind() {
+        ErrorKind::NotFound | ErrorKind::Corrupt => Ok(error_chain(&e)),
+        _ => Err(e),
     }
-    let b = get_backup(&bucket, basename, &index.Blocks);
-    pin_mut!(b);
+}
 
-    let mut f = File::create(dst_path)?;
-    while let Some((offset, chunk)) = b.next().await.transpose()? {
-        f.seek(std::io::SeekFrom::Start(offset))?;
-        f.write_all(&chunk)?;
+async fn list(target: &dyn BackupTarget, root: &str, output: OutputFormat) -> Result<(), Error> {
+    let mut volumes = Vec::new();
+    for volume_dir in backupstore::volume_dirs(target, root).await? {
+        let volume = match load_volume_cfg(target, &volume_dir).await {
+            Ok(volume) => volume,
+            Err(e) => {
+                let error = entry_error(e)?;
+                let name = volume_dir.trim_end_matches('/').rsplit('/').next();
+                if output == OutputFormat::Text {
+                    println!("{}	error={}", name.unwrap_or_default(), error);
+                }
+                volumes.push(serde_json::json!({ "name": name, "error": error }));
+                continue;
+            }
+        };
+        if output == OutputFormat::Text {
+            println!(
+                "{}\tsize={}\tcreated={}\tlast_backup={}",
+                volume.Name, volume.Size, volume.CreatedTime, volume.LastBackupName
+            );
+        }
+        let mut backups = Vec::new();
+        for key in backupstore::backup_cfg_keys(target, &volume_dir).await? {
+            let backup = match load_backup_cfg(target, &key).await {
+                Ok(backup) => backup,
+                Err(e) => {
+                    let error = entry_error(e)?;
+                    if output == OutputFormat::Text {
+                        println!("  {}	error={}", key, error);
+                    }
+                    backups.push(serde_json::json!({ "key": key, "error": error }));
+                    continue;
+                }
+            };
+            if output == OutputFormat::Text {
+                println!(
+                    "  {}\tsnapshot={}\tcreated={}\tsize={}",
+                    backup.Name, backup.SnapshotName, backup.CreatedTime, backup.Size
+                );
+            }
+            backups.push(serde_json::json!({
+                "name": backup.Name,
+                "snapshot": backup.SnapshotName,
+                "created": backup.CreatedTime,
+                "size": backup.Size,
+            }));
+        }
+        volumes.push(serde_json::json!({
+            "name": volume.Name,
+            "size": volume.Size,
+            "created": volume.CreatedTime,
+            "last_backup": volume.LastBackupName,
+            "backups": backups,
+        }));
+    }
+    if output == OutputFormat::Json {
+        output::print_json(&volumes);
     }
     Ok(())
 }
+
+async fn run(cli: &Cli) -> Result<ExitCode, Error> {
+    let target = cli.target.open()?;
+    let target = target.as_ref();
+    let root = cli.target.root();
+    match &cli.command {
+        Command::Restore(args) => restore(target, &root, args, cli.output).await?,
+        Command::Verify(args) => return verify(target, &root, args, cli.output).await,
+        Command::Check(args) => return check(target, &root, args, cli.output).await,
+        Command::Diff(args) => return diff(target, &root, args, cli.output).await,
+        Command::Inspect { backup } => inspect(target, &root, backup, cli.output).await?,
+        Command::List => list(target, &root, cli.output).await?,
+    }
+    Ok(ExitCode::SUCCESS)
+}
+
+#[tokio::main]
+async fn main() -> ExitCode {
+    let cli = Cli::parse();
+    match run(&cli).await {
+        Ok(code) => code,
+        Err(e) => output::report_error(cli.output, &e),
+    }
+}
@@ -0,0 +1,75 @@
+use get_longhorn_backup::error::Error;
+use serde::Serialize;
+use std::process::ExitCode;
+
+pub const EXIT_CODES_HELP: &str = "\
+Exit status:
+  0  success
+  1  any other failure
+  2  usage error
+  3  authentication or authorization failure
+  4  backup, volume or block not found
+  5  corrupt backup: bad index, undecodable block or checksum mismatch,
+     or any bad block or unreadable backup found by verify or check
+  6  local I/O error
+  7  diff found the local copy differs from the backup";
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, clap::ValueEnum)]
+pub enum OutputFormat {
+    Text,
+    Json,
+}
+
+pub fn print_json(value: &impl Serialize) {
+    println!("{}", serde_json::to_string(value).unwrap());
+}
+
+fn error_json(e: &Error) -> serde_json::Value {
+    let kind = e.kind();
+    serde_json::json!({
+        "kind": kind.name(),
+        "message": e.to_string(),
+        "exit_code": kind.exit_code(),
+    })
+}
+
+fn print_error_text(e: &Error) {
+    eprintln!("Error: {}", e);
+    let mut source = std::error::Error::source(e);
+    while let Some(e) = source {
+        eprintln!("  caused by: {}", e);
+        source = e.source();
+    }
+}
+
+pub fn report_error(format: OutputFormat, e: &Error) -> ExitCode {
+    match format {
+        OutputFormat::Text => print_error_text(e),
+        OutputFormat::Json => print_json(&serde_json::json!({ "error": error_json(e) })),
+    }
+    ExitCode::from(e.kind().exit_code())
+}
+
+// The outcome of a command whose summary stands even when it fails, as
+// one JSON document with the error inside.
+pub fn report_summary(
+    format: OutputFormat,
+    summary: &impl Serialize,
+    e: Option<Error>,
+) -> ExitCode {
+    match format {
+        OutputFormat::Text => {
+            if let Some(e) = &e {
+                print_error_text(e);
+            }
+        }
+        OutputFormat::Json => {
+            let mut value = serde_json::to_value(summary).unwrap();
+            if let Some(e) = &e {
+                value["error"] = error_json(e);
+            }
+            print_json(&value);
+        }
+    }
+    e.map_or(ExitCode::SUCCESS, |e| ExitCode::from(e.kind().exit_code()))
+}
@@ -0,0 +1,133 @@
+use indicatif::{ProgressBar, ProgressStyle};
+use std::io::IsTerminal;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+// How often to print a progress line when not drawing a bar.
+const LOG_INTERVAL: Duration = Duration::from_secs(10);
+
+// The bar being drawn, if any, so that other messages on stderr can go
+// above it instead of through it.
+static BAR: Mutex<Option<ProgressBar>> = Mutex::new(None);
+
+// Like eprintln!, but leaves the progress bar intact.
+pub fn eprintln(message: &str) {
+    match &*BAR.lock().unwrap() {
+        Some(bar) => bar.suspend(|| eprintln!("{}", message)),
+        None => eprintln!("{}", message),
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, clap::ValueEnum)]
+pub enum ProgressMode {
+    // A bar on a terminal, log lines otherwise.
+    Auto,
+    Bar,
+    Log,
+    None,
+}
+
+pub struct Progress {
+    mode: ProgressMode,
+    bar: Option<ProgressBar>,
+    total_blocks: u64,
+    blocks: u64,
+    downloaded: u64,
+    decompressed: u64,
+    start: Instant,
+    last_log: Instant,
+}
+
+impl Progress {
+    pub fn new(mode: ProgressMode, total_blocks: u64) -> Self {
+        let mode = match mode {
+            ProgressMode::Auto if std::io::stderr().is_terminal() => ProgressMode::Bar,
+            ProgressMode::Auto => ProgressMode::Log,
+            mode => mode,
+        };
+        let bar = (mode == ProgressMode::Bar).then(|| {
+            let bar = ProgressBar::new(total_blocks);
+            bar.set_style(
+                ProgressStyle::with_template("{wide_bar} {pos}/{len} blocks {msg} ETA {eta}")
+                    .unwrap(),
+            );
+            *BAR.lock().unwrap() = Some(bar.clone());
+            bar
+        });
+        let now = Instant::now();
+        Self {
+            mode,
+            bar,
+            total_blocks,
+            blocks: 0,
+            downloaded: 0,
+            decompressed: 0,
+            start: now,
+            last_log: now,
+        }
+    }
+
+    pub fn block(&mut self, downloaded: usize, decompressed: usize) {
+        self.blocks += 1;
+        self.downloaded += downloaded as u64;
+        self.decompressed += decompressed as u64;
+        match self.mode {
+            ProgressMode::Bar => {
+                let bar = self.bar.as_ref().unwrap();
+                bar.set_message(format!(
+                    "{} MiB downloaded, {} MiB written, {:.1} MiB/s",
+                    self.downloaded >> 20,
+                    self.decompressed >> 20,
+                    self.rate() / (1 << 20) as f64
+                ));
+                bar.inc(1);
+            }
+            ProgressMode::Log if self.last_log.elapsed() >= LOG_INTERVAL => {
+                self.last_log = Instant::now();
+                self.log();
+            }
+            _ => (),
+        }
+    }
+
+    pub fn finish(&self) {
+        match self.mode {
+            ProgressMode::Bar => self.bar.as_ref().unwrap().finish(),
+            ProgressMode::Log => self.log(),
+            _ => (),
+        }
+    }
+
+    // Decompressed bytes per second.
+    fn rate(&self) -> f64 {
+        self.decompressed as f64 / self.start.elapsed().as_secs_f64()
+    }
+
+    fn eta(&self) -> Option<Duration> {
+        if self.blocks == 0 {
+            return None;
+        }
+        let per_block = self.start.elapsed() / self.blocks as u32;
+        Some(per_block * (self.total_blocks - self.blocks) as u32)
+    }
+
+    fn log(&self) {
+        eprintln!(
+            "progress blocks={}/{} downloaded_bytes={} written_bytes={} bytes_per_sec={:.0} eta_secs={}",
+            self.blocks,
+            self.total_blocks,
+            self.downloaded,
+            self.decompressed,
+            self.rate(),
+            self.eta().map_or("unknown".to_owned(), |eta| eta.as_secs().to_string())
+        );
+    }
+}
+
+impl Drop for Progress {
+    fn drop(&mut self) {
+        if self.bar.is_some() {
+            BAR.lock().unwrap().take();
+        }
+    }
+}
@@ -0,0 +1,302 @@
+use crate::checksum::{ChecksumMismatch, OnMismatch};
+use crate::compression::CompressionMethod;
+use crate::error::Error;
+use crate::journal::{Journal, WrittenBlocks};
+use crate::target::BackupTarget;
+use crate::writer::{Destination, GapFill};
+use crate::{check_blocks, get_backup, load_backup_cfg, sparse_regions, Observer};
+use futures_util::{pin_mut, StreamExt};
+use serde::Serialize;
+use std::path::{Path, PathBuf};
+use std::time::Instant;
+
+// How many written blocks to sync to the destination at a time before
+// recording them in the journal.
+const JOURNAL_COMMIT_BLOCKS: usize = 64;
+
+#[derive(Clone, Debug)]
+pub struct RestoreOptions {
+    // Number of blocks to fetch and decompress concurrently.
+    pub jobs: usize,
+    pub on_mismatch: OnMismatch,
+    // Punch holes for all-zero blocks instead of writing them.
+    pub punch_zeroes: bool,
+    // The destination is an existing block device rather than a file.
+    pub block_device: bool,
+    // On a block device, what to do with regions the backup has no data
+    // for.
+    pub gaps: GapFill,
+    // Continue an interrupted restore from its journal.
+    pub resume: bool,
+    // Defaults to <dst>.journal for a file and no journal for a block
+    // device.
+    pub journal: Option<PathBuf>,
+}
+
+#[derive(Serialize, Default)]
+pub struct RestoreSummary {
+    pub backup: String,
+    pub blocks: usize,
+    pub blocks_written: usize,
+    pub blocks_resumed: usize,
+    pub bytes_downloaded: u64,
+    pub bytes_written: u64,
+    pub duration_secs: f64,
+    pub checksum_mismatches: Vec<ChecksumMismatch>,
+}
+
+// Restores the backup at backup_cfg, in volume_dir, into dst.
+pub async fn restore(
+    target: &dyn BackupTarget,
+    volume_dir: &str,
+    backup_cfg: &str,
+    dst: &Path,
+    options: &RestoreOptions,
+    observer: &mut dyn Observer,
+) -> Result<RestoreSummary, Error> {
+    let start = Instant::now();
+    let index = load_backup_cfg(target, backup_cfg).await?;
+    check_blocks(&index.Blocks, index.Size)?;
+    let compression = index.CompressionMethod.parse::<CompressionMethod>()?;
+
+    let journal_path = match &options.journal {
+        Some(path) => Some(path.clone()),
+        None if options.block_device => None,
+        None => {
+            let mut path = dst.to_owned().into_os_string();
+            path.push(".journal");
+            Some(PathBuf::from(path))
+        }
+    };
+    if options.resume && journal_path.is_none() {
+        return Err(Error::Usage(
+            "Resuming onto a block device needs a journal".to_owned(),
+        ));
+    }
+    // Without a journal there is nothing to resume, and a file is
+    // restored from scratch.
+    let resuming = options.resume && journal_path.as_ref().is_some_and(|p| p.exists());
+    if resuming && !options.block_device && !dst.exists() {
+        return Err(Error::Usage(format!(
+            "Cannot resume: {} no longer exists; remove {} to restore from scratch",
+            dst.display(),
+            journal_path.as_ref().unwrap().display()
+        )));
+    }
+
+    let mut dst_file = if options.block_device {
+        Destination::open_device(dst, index.Size, options.punch_zeroes)
+    } else if resuming {
+        Destination::open(dst, index.Size, options.punch_zeroes)
+    } else {
+        Destination::create(dst, index.Size, options.punch_zeroes)
+    }
+    .map_err(Error::io(dst))?;
+
+    // The journal only describes the very file or device it was written
+    // alongside; one that replaced it holds none of those blocks.
+    let header = format!(
+        "get-longhorn-backup {} {} {}",
+        backup_cfg,
+        index.Size,
+        dst_file.identity().map_err(Error::io(dst))?
+    );
+    let (mut journal, done) = match &journal_path {
+        Some(path) if resuming => {
+            let (journal, done) = Journal::resume(path, &header)?;
+            (Some(journal), done)
+        }
+        Some(path) => (Some(Journal::create(path, &header)?), WrittenBlocks::new()),
+        None => (None, WrittenBlocks::new()),
+    };
+    dst_file
+        .fill_gaps(&sparse_regions(&index.Blocks, index.Size), options.gaps)
+        .map_err(Error::io(dst))?;
+
+    let todo: Vec<_> = index
+        .Blocks
+        .iter()
+        .filter(|b| !done.contains(&(b.Offset, b.BlockChecksum.clone())))
+        .collect();
+    observer.start(todo.len());
+    let mut summary = RestoreSummary {
+        backup: backup_cfg.to_owned(),
+        blocks: index.Blocks.len(),
+        blocks_resumed: index.Blocks.len() - todo.len(),
+        ..Default::default()
+    };
+    let b = get_backup(
+        target,
+        volume_dir,
+        todo,
+        compression,
+        options.on_mismatch,
+        options.jobs,
+    );
+    pin_mut!(b);
+
+    let mut unsynced = 0;
+    while let Some(fetched) = b.next().await.transpose()? {
+        let block = fetched.block;
+        dst_file
+            .write_block(block.Offset, &fetched.data)
+            .map_err(Error::io(dst))?;
+        observer.block(fetched.stored_len, fetched.data.len());
+        summary.blocks_written += 1;
+        summary.bytes_downloaded += fetched.stored_len as u64;
+        summary.bytes_written += fetched.data.len() as u64;
+        if let Some(mismatch) = fetched.mismatch {
+            observer.mismatch(&mismatch);
+            summary.checksum_mismatches.push(mismatch);
+        }
+        if let Some(journal) = &mut journal {
+            journal.record(block.Offset, &block.BlockChecksum);
+            unsynced += 1;
+            if unsynced == JOURNAL_COMMIT_BLOCKS {
+                dst_file.sync().map_err(Error::io(dst))?;
+                journal.commit()?;
+                unsynced = 0;
+            }
+        }
+    }
+    dst_file.finish().map_err(Error::io(dst))?;
+    if let Some(journal) = journal {
+        journal.remove()?;
+    }
+    summary.duration_secs = start.elapsed().as_secs_f64();
+    Ok(summary)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::checksum::longhorn_checksum;
+    use crate::config::BackupBlock;
+    use crate::fixture::{temp_path, Backupstore};
+    use crate::BLOCK_SIZE;
+
+    fn options() -> RestoreOptions {
+        RestoreOptions {
+            jobs: 2,
+            on_mismatch: OnMismatch::Fail,
+            punch_zeroes: false,
+            block_device: false,
+            gaps: GapFill::Leave,
+            resume: false,
+            journal: None,
+        }
+    }
+
+    fn block_data(byte: u8) -> Vec<u8> {
+        vec![byte; BLOCK_SIZE as usize]
+    }
+
+    // A backup whose second block is not stored yet, so restoring it fails
+    // part way and leaves a journal behind.
+    fn interrupted(store: &Backupstore) -> String {
+        let a = store.put_block(0, &block_data(1), CompressionMethod::Lz4);
+        let b = BackupBlock {
+            Offset: 2 * BLOCK_SIZE,
+            BlockChecksum: longhorn_checksum(&block_data(2)),
+        };
+        store.put_backup("b", 3 * BLOCK_SIZE, "lz4", vec![a, b])
+    }
+
+    #[tokio::test]
+    async fn resumes_interrupted_restore() {
+        let store = Backupstore::new("restore-resume");
+        let cfg = interrupted(&store);
+        let dst = temp_path("restore-resume.img");
+        let journal = temp_path("restore-resume.img.journal");
+        let mut options = options();
+        let e = restore(
+            &store.target,
+            &store.volume_dir,
+            &cfg,
+            &dst,
+            &options,
+            &mut (),
+        )
+        .await;
+        assert_eq!(
+            e.err().map(|e| e.kind()),
+            Some(crate::error::ErrorKind::NotFound)
+        );
+        assert!(journal.exists());
+
+        store.put_block(2 * BLOCK_SIZE, &block_data(2), CompressionMethod::Lz4);
+        options.resume = true;
+        let summary = restore(
+            &store.target,
+            &store.volume_dir,
+            &cfg,
+            &dst,
+            &options,
+            &mut (),
+        )
+        .await
+        .unwrap();
+        assert_eq!(summary.blocks, 2);
+        assert!(!journal.exists());
+        let restored = std::fs::read(&dst).unwrap();
+        std::fs::remove_file(&dst).unwrap();
+        assert_eq!(restored.len() as u64, 3 * BLOCK_SIZE);
+        assert_eq!(restored[..BLOCK_SIZE as usize], block_data(1));
+        assert_eq!(
+            restored[BLOCK_SIZE as usize..2 * BLOCK_SIZE as usize],
+            block_data(0)
+        );
+        assert_eq!(restored[2 * BLOCK_SIZE as usize..], block_data(2));
+    }
+
+    #[tokio::test]
+    async fn resumes_only_into_the_same_file() {
+        let store = Backupstore::new("restore-stale");
+        let cfg = interrupted(&store);
+        let dst = temp_path("restore-stale.img");
+        let moved = temp_path("restore-stale.img.old");
+        let journal = temp_path("restore-stale.img.journal");
+        let mut options = options();
+        let e = restore(
+            &store.target,
+            &store.volume_dir,
+            &cfg,
+            &dst,
+            &options,
+            &mut (),
+        )
+        .await;
+        assert!(e.is_err());
+        store.put_block(2 * BLOCK_SIZE, &block_data(2), CompressionMethod::Lz4);
+        options.resume = true;
+
+        // The journal says nothing about a file that took the old one's
+        // place, nor about no file at all.
+        std::fs::rename(&dst, &moved).unwrap();
+        std::fs::write(&dst, b"").unwrap();
+        let replaced = restore(
+            &store.target,
+            &store.volume_dir,
+            &cfg,
+            &dst,
+            &options,
+            &mut (),
+        )
+        .await;
+        std::fs::remove_file(&dst).unwrap();
+        let gone = restore(
+            &store.target,
+            &store.volume_dir,
+            &cfg,
+            &dst,
+            &options,
+            &mut (),
+        )
+        .await;
+        std::fs::remove_file(&moved).unwrap();
+        std::fs::remove_file(&journal).unwrap();
+        assert!(matches!(replaced, Err(Error::JournalMismatch { .. })));
+        assert!(matches!(gone, Err(Error::Usage(_))));
+        assert!(!dst.exists());
+    }
+}
@@ -0,0 +1,147 @@
+use crate::error::Error;
+use base64::Engine;
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::io::Read;
+use std::path::Path;
+
+// The Kubernetes secret Longhorn reads a backup target's settings from,
+// e.g. AWS_ACCESS_KEY_ID, AWS_SECRET_ACCESS_KEY, AWS_ENDPOINTS and
+// AWS_CERT.
+#[derive(Clone, Debug, Default)]
+pub struct BackupTargetSecret {
+    data: BTreeMap<String, String>,
+}
+
+#[derive(Deserialize)]
+struct SecretObject {
+    #[serde(default)]
+    data: BTreeMap<String, String>,
+    #[serde(default, rename = "stringData")]
+    string_data: BTreeMap<String, String>,
+}
+
+impl BackupTargetSecret {
+    // Either the secret as mounted into a pod, a directory with one file
+    // per key, or the output of `kubectl get secret -o json` (- for stdin).
+    pub fn load(path: &Path) -> Result<Self, Error> {
+        if path.is_dir() {
+            Self::from_dir(path)
+        } else {
+            let json = if path == Path::new("-") {
+                let mut json = Vec::new();
+                std::io::stdin()
+                    .read_to_end(&mut json)
+                    .map_err(Error::io(path))?;
+                json
+            } else {
+                std::fs::read(path).map_err(Error::io(path))?
+            };
+            Self::from_json(&json).map_err(|e| {
+                Error::Usage(format!("Could not read secret {}: {}", path.display(), e))
+            })
+        }
+    }
+
+    pub fn from_dir(path: &Path) -> Result<Self, Error> {
+        let mut data = BTreeMap::new();
+        for entry in std::fs::read_dir(path).map_err(Error::io(path))? {
+            let entry = entry.map_err(Error::io(path))?;
+            let name = entry.file_name().to_string_lossy().into_owned();
+            // Kubernetes keeps the real files in ..data and the like.
+            if name.starts_with('.') {
+                continue;
+            }
+            let file = entry.path();
+            if !file.is_file() {
+                continue;
+            }
+            let value = std::fs::read_to_string(&file).map_err(Error::io(&file))?;
+            data.insert(name, value);
+        }
+        Ok(Self { data })
+    }
+
+    pub fn from_json(json: &[u8]) -> Result<Self, String> {
+        let object: SecretObject = serde_json::from_slice(json).map_err(|e| e.to_string())?;
+        let mut data = object.string_data;
+        for (key, value) in object.data {
+            let value = base64::engine::general_purpose::STANDARD
+                .decode(value)
+                .ok()
+                .and_then(|v| String::from_utf8(v).ok())
+                .ok_or_else(|| format!("{} is not base64-encoded text", key))?;
+            data.insert(key, value);
+        }
+        Ok(Self { data })
+    }
+
+    // Values commonly end in a newline when the secret was made from files.
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.data
+            .get(key)
+            .map(|v| v.trim())
+            .filter(|v| !v.is_empty())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixture::temp_path;
+
+    #[test]
+    fn reads_kubectl_json() {
+        let secret = BackupTargetSecret::from_json(
+            br#"{
+                "apiVersion": "v1",
+                "kind": "Secret",
+                "data": {
+                    "AWS_ACCESS_KEY_ID": "bG9uZ2hvcm4=",
+                    "AWS_ENDPOINTS": "aHR0cHM6Ly9taW5pbzo5MDAwCg=="
+                },
+                "stringData": {"AWS_SECRET_ACCESS_KEY": "s3cret", "AWS_CERT": ""}
+            }"#,
+        )
+        .unwrap();
+        assert_eq!(secret.get("AWS_ACCESS_KEY_ID"), Some("longhorn"));
+        assert_eq!(secret.get("AWS_ENDPOINTS"), Some("https://minio:9000"));
+        assert_eq!(secret.get("AWS_SECRET_ACCESS_KEY"), Some("s3cret"));
+        assert_eq!(secret.get("AWS_CERT"), None);
+        assert_eq!(secret.get("AWS_SESSION_TOKEN"), None);
+    }
+
+    #[test]
+    fn rejects_invalid_base64() {
+        let e = BackupTargetSecret::from_json(br#"{"data": {"AWS_ACCESS_KEY_ID": "not base64!"}}"#)
+            .unwrap_err();
+        assert_eq!(e, "AWS_ACCESS_KEY_ID is not base64-encoded text");
+        assert!(BackupTargetSecret::from_json(b"apiVersion: v1").is_err());
+    }
+
+    #[test]
+    fn reads_mounted_secret() {
+        // How the kubelet lays out a mounted secret: the files live in a
+        // timestamped directory that ..data links to, and each key is a
+        // link into ..data.
+        let dir = temp_path("secret");
+        let _ = std::fs::remove_dir_all(&dir);
+        let files = dir.join("..2026_10_16_12_00_00.000000001");
+        std::fs::create_dir_all(&files).unwrap();
+        std::fs::write(files.join("AWS_ACCESS_KEY_ID"), "longhorn\n").unwrap();
+        std::fs::write(files.join("AWS_SECRET_ACCESS_KEY"), "s3cret").unwrap();
+        std::os::unix::fs::symlink(files.file_name().unwrap(), dir.join("..data")).unwrap();
+        for key in ["AWS_ACCESS_KEY_ID", "AWS_SECRET_ACCESS_KEY"] {
+            std::os::unix::fs::symlink(format!("..data/{}", key), dir.join(key)).unwrap();
+        }
+
+        let secret = BackupTargetSecret::from_dir(&dir).unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(
+            secret.data.keys().collect::<Vec<_>>(),
+            ["AWS_ACCESS_KEY_ID", "AWS_SECRET_ACCESS_KEY"]
+        );
+        assert_eq!(secret.get("AWS_ACCESS_KEY_ID"), Some("longhorn"));
+        assert_eq!(secret.get("AWS_SECRET_ACCESS_KEY"), Some("s3cret"));
+    }
+}
@@ -0,0 +1,306 @@
+use crate::error::Error;
+use async_trait::async_trait;
+use rand::Rng;
+use std::future::Future;
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::sync::Arc;
+use std::time::Duration;
+
+mod azblob;
+mod fs;
+mod http;
+mod s3;
+
+pub use self::azblob::AzureTarget;
+pub use self::fs::FsTarget;
+pub use self::http::TlsOptions;
+pub use self::s3::{Addressing, S3Target};
+
+// Where a backupstore lives. Keys are '/'-separated paths from the root
+// of the target, as in an S3 bucket.
+#[async_trait]
+pub trait BackupTarget: Send + Sync {
+    async fn get(&self, key: &str) -> Result<Vec<u8>, Error>;
+
+    // The immediate "subdirectories" of prefix, which must end in a slash.
+    // Each one ends in a slash too.
+    async fn list_dirs(&self, prefix: &str) -> Result<Vec<String>, Error>;
+
+    // Keys and sizes of the objects directly under prefix.
+    async fn list_files(&self, prefix: &str) -> Result<Vec<(String, u64)>, Error>;
+}
+
+// A backup target as Longhorn's backup-target setting names it, with the
+// backupstore directory under the path.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum TargetUrl {
+    // s3://bucket@region/path/
+    S3 {
+        bucket: String,
+        region: String,
+        prefix: String,
+    },
+    // azblob://container@endpoint-suffix/path/, e.g. core.windows.net
+    Azure {
+        container: String,
+        endpoint_suffix: String,
+        prefix: String,
+    },
+    // file:///path or just /path: a local directory or a mounted NFS
+    // export.
+    Dir(PathBuf),
+}
+
+#[derive(Debug)]
+pub struct InvalidTargetUrl(String);
+
+impl std::fmt::Display for InvalidTargetUrl {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "Invalid backup target {:?}, expected s3://bucket@region/path/, \
+             azblob://container@core.windows.net/path/, file:///path or an \
+             absolute path; NFS targets must be mounted first",
+            self.0
+        )
+    }
+}
+
+impl std::error::Error for InvalidTargetUrl {}
+
+impl FromStr for TargetUrl {
+    type Err = InvalidTargetUrl;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || InvalidTargetUrl(s.to_owned());
+        if let Some(path) = s
+            .strip_prefix("file://")
+            .or(s.starts_with('/').then_some(s))
+        {
+            if !path.starts_with('/') {
+                return Err(invalid());
+            }
+            return Ok(Self::Dir(PathBuf::from(path)));
+        }
+        let (scheme, rest) = s.split_once("://").ok_or_else(invalid)?;
+        let (authority, path) = rest.split_once('/').unwrap_or((rest, ""));
+        let (user, host) = authority.split_once('@').ok_or_else(invalid)?;
+        if user.is_empty() || host.is_empty() {
+            return Err(invalid());
+        }
+        let prefix = path.trim_matches('/').to_owned();
+        match scheme {
+            "s3" => Ok(Self::S3 {
+                bucket: user.to_owned(),
+                region: host.to_owned(),
+                prefix,
+            }),
+            "azblob" => Ok(Self::Azure {
+                container: user.to_owned(),
+                endpoint_suffix: host.to_owned(),
+                prefix,
+            }),
+            _ => Err(invalid()),
+        }
+    }
+}
+
+// A failed attempt at a request that is about to be tried again.
+pub struct RetryEvent<'a> {
+    pub what: &'a str,
+    // The attempt that failed, counting from 1.
+    pub attempt: u32,
+    pub attempts: u32,
+    pub delay: Duration,
+    pub error: &'a dyn std::fmt::Display,
+}
+
+pub type OnRetry = Arc<dyn Fn(&RetryEvent) + Send + Sync>;
+
+#[derive(Clone)]
+pub struct RetryPolicy {
+    // Total tries per request, including the first.
+    pub attempts: u32,
+    pub base_delay: Duration,
+    pub max_delay: Duration,
+    // Called before each retry; retries are silent without it.
+    pub on_retry: Option<OnRetry>,
+}
+
+impl RetryPolicy {
+    // Exponential backoff with full jitter.
+    fn delay(&self, attempt: u32) -> Duration {
+        let ceiling = self
+            .base_delay
+            .saturating_mul(1 << (attempt - 1).min(20))
+            .min(self.max_delay);
+        ceiling.mul_f64(rand::thread_rng().gen())
+    }
+
+    // Runs f until it succeeds, fails for good or runs out of attempts.
+    pub(crate) async fn run<T, E, F, Fut>(
+        &self,
+        what: &str,
+        is_retryable: fn(&E) -> bool,
+        f: F,
+    ) -> Result<T, E>
+    where
+        E: std::fmt::Display,
+        F: Fn() -> Fut,
+        Fut: Future<Output = Result<T, E>>,
+    {
+        let mut attempt = 1;
+        loop {
+            match f().await {
+                Err(e) if attempt < self.attempts && is_retryable(&e) => {
+                    let delay = self.delay(attempt);
+                    if let Some(on_retry) = &self.on_retry {
+                        on_retry(&RetryEvent {
+                            what,
+                            attempt,
+                            attempts: self.attempts,
+                            delay,
+                            error: &e,
+                        });
+                    }
+                    tokio::time::sleep(delay).await;
+                    attempt += 1;
+                }
+                r => return r,
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::Cell;
+    use std::sync::Mutex;
+
+    fn policy(attempts: u32, retries: Arc<Mutex<Vec<u32>>>) -> RetryPolicy {
+        RetryPolicy {
+            attempts,
+            base_delay: Duration::ZERO,
+            max_delay: Duration::ZERO,
+            on_retry: Some(Arc::new(move |e| retries.lock().unwrap().push(e.attempt))),
+        }
+    }
+
+    fn is_busy(e: &&str) -> bool {
+        *e == "busy"
+    }
+
+    // Runs a request that fails with each of errors in turn, then succeeds.
+    async fn run(
+        policy: &RetryPolicy,
+        errors: &[&'static str],
+    ) -> (Result<u32, &'static str>, u32) {
+        let calls = Cell::new(0);
+        let result = policy
+            .run("key", is_busy, || {
+                let call = calls.get();
+                calls.set(call + 1);
+                async move { errors.get(call as usize).map_or(Ok(call), |&e| Err(e)) }
+            })
+            .await;
+        (result, calls.get())
+    }
+
+    #[tokio::test]
+    async fn retries_retryable_errors() {
+        let retries = Arc::default();
+        let policy = policy(5, Arc::clone(&retries));
+        assert_eq!(run(&policy, &["busy", "busy"]).await, (Ok(2), 3));
+        assert_eq!(*retries.lock().unwrap(), [1, 2]);
+    }
+
+    #[tokio::test]
+    async fn stops_on_other_errors() {
+        let retries = Arc::default();
+        let policy = policy(5, Arc::clone(&retries));
+        assert_eq!(
+            run(&policy, &["busy", "denied", "busy"]).await,
+            (Err("denied"), 2)
+        );
+        assert_eq!(*retries.lock().unwrap(), [1]);
+    }
+
+    #[tokio::test]
+    async fn gives_up_after_attempts() {
+        let retries = Arc::default();
+        let policy = policy(3, Arc::clone(&retries));
+        assert_eq!(run(&policy, &["busy"; 5]).await, (Err("busy"), 3));
+        assert_eq!(*retries.lock().unwrap(), [1, 2]);
+        let once = RetryPolicy {
+            attempts: 1,
+            ..policy
+        };
+        assert_eq!(run(&once, &["busy"]).await, (Err("busy"), 1));
+    }
+
+    #[test]
+    fn parses_s3() {
+        assert_eq!(
+            "s3://backups@us-east-1/longhorn/"
+                .parse::<TargetUrl>()
+                .unwrap(),
+            TargetUrl::S3 {
+                bucket: "backups".to_owned(),
+                region: "us-east-1".to_owned(),
+                prefix: "longhorn".to_owned(),
+            }
+        );
+        assert_eq!(
+            "s3://backups@minio/".parse::<TargetUrl>().unwrap(),
+            TargetUrl::S3 {
+                bucket: "backups".to_owned(),
+                region: "minio".to_owned(),
+                prefix: String::new(),
+            }
+        );
+    }
+
+    #[test]
+    fn parses_azblob() {
+        assert_eq!(
+            "azblob://longhorn@core.windows.net/a/b"
+                .parse::<TargetUrl>()
+                .unwrap(),
+            TargetUrl::Azure {
+                container: "longhorn".to_owned(),
+                endpoint_suffix: "core.windows.net".to_owned(),
+                prefix: "a/b".to_owned(),
+            }
+        );
+    }
+
+    #[test]
+    fn parses_directories() {
+        assert_eq!(
+            "file:///mnt/nfs/backups".parse::<TargetUrl>().unwrap(),
+            TargetUrl::Dir(PathBuf::from("/mnt/nfs/backups"))
+        );
+        assert_eq!(
+            "/mnt/nfs".parse::<TargetUrl>().unwrap(),
+            TargetUrl::Dir(PathBuf::from("/mnt/nfs"))
+        );
+    }
+
+    #[test]
+    fn rejects_invalid() {
+        for url in [
+            "",
+            "backups",
+            "file://relative/path",
+            "s3://backups/path",
+            "s3://@us-east-1/",
+            "s3://backups@/",
+            "nfs://server:/export",
+            "gs://bucket@region/",
+        ] {
+            assert!(url.parse::<TargetUrl>().is_err(), "{:?}", url);
+        }
+    }
+}
@@ -0,0 +1,293 @@
+use super::{http, BackupTarget, RetryPolicy};
+use crate::error::Error;
+use async_trait::async_trait;
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::Engine;
+use hmac::{Hmac, Mac};
+use reqwest::RequestBuilder;
+use serde::Deserialize;
+use sha2::Sha256;
+use std::time::SystemTime;
+
+// The Blob service REST API version we speak.
+const API_VERSION: &str = "2021-08-06";
+
+// A container in an Azure Blob Storage account, or anything speaking the
+// same API such as Azurite. Without a key, requests go unsigned, which
+// works for containers with public access.
+pub struct AzureTarget {
+    client: reqwest::Client,
+    // The account's blob endpoint, without a trailing slash.
+    service_url: String,
+    account: String,
+    key: Option<Vec<u8>>,
+    container: String,
+    retry: RetryPolicy,
+}
+
+#[derive(Deserialize)]
+struct EnumerationResults {
+    #[serde(rename = "Blobs", default)]
+    blobs: Blobs,
+    #[serde(rename = "NextMarker", default)]
+    next_marker: String,
+}
+
+#[derive(Deserialize, Default)]
+struct Blobs {
+    #[serde(rename = "Blob", default)]
+    blobs: Vec<Blob>,
+    #[serde(rename = "BlobPrefix", default)]
+    prefixes: Vec<Named>,
+}
+
+#[derive(Deserialize)]
+struct Blob {
+    #[serde(rename = "Name")]
+    name: String,
+    #[serde(rename = "Properties")]
+    properties: BlobProperties,
+}
+
+#[derive(Deserialize)]
+struct BlobProperties {
+    #[serde(rename = "Content-Length")]
+    content_length: u64,
+}
+
+#[derive(Deserialize)]
+struct Named {
+    #[serde(rename = "Name")]
+    name: String,
+}
+
+impl AzureTarget {
+    // Where Longhorn looks for an account: https://<account>.blob.<suffix>
+    // for azblob://container@<suffix>/, or <endpoint>/<account> when
+    // AZBLOB_ENDPOINT points somewhere else, as it does for Azurite.
+    pub fn new(
+        client: reqwest::Client,
+        account: &str,
+        key: Option<&str>,
+        endpoint: Option<&str>,
+        endpoint_suffix: &str,
+        container: &str,
+        retry: RetryPolicy,
+    ) -> Result<Self, Error> {
+        let service_url = match endpoint {
+            Some(endpoint) => format!("{}/{}", endpoint.trim_end_matches('/'), account),
+            None => format!("https://{}.blob.{}", account, endpoint_suffix),
+        };
+        let key = key
+            .map(|key| BASE64.decode(key))
+            .transpose()
+            .map_err(|_| Error::Usage("The Azure account key is not valid base64".to_owned()))?;
+        Ok(Self {
+            client,
+            service_url,
+            account: account.to_owned(),
+            key,
+            container: container.to_owned(),
+            retry,
+        })
+    }
+
+    // Sends a GET for the path below the container, with retries.
+    async fn get_path(
+        &self,
+        what: &str,
+        path: &str,
+        query: &[(&str, &str)],
+    ) -> Result<Vec<u8>, Error> {
+        let url = format!(
+            "{}/{}{}",
+            self.service_url,
+            self.container,
+            encode_path(path)
+        );
+        http::get(&self.retry, what, || {
+            self.sign(self.client.get(&url).query(query), &url, query)
+        })
+        .await
+    }
+
+    // Shared Key authorization for a request without a body.
+    fn sign(&self, request: RequestBuilder, url: &str, query: &[(&str, &str)]) -> RequestBuilder {
+        let date = httpdate::fmt_http_date(SystemTime::now());
+        let request = request
+            .header("x-ms-date", &date)
+            .header("x-ms-version", API_VERSION);
+        let Some(key) = &self.key else {
+            return request;
+        };
+        let string_to_sign = string_to_sign(&self.account, &date, url, query);
+        request.header(
+            "Authorization",
+            format!(
+                "SharedKey {}:{}",
+                self.account,
+                signature(key, &string_to_sign)
+            ),
+        )
+    }
+
+    // Every page of a listing of the blobs directly under prefix.
+    async fn list(&self, prefix: &str) -> Result<Vec<EnumerationResults>, Error> {
+        let mut pages = Vec::new();
+        let mut marker = String::new();
+        loop {
+            let mut query = vec![
+                ("comp", "list"),
+                ("delimiter", "/"),
+                ("prefix", prefix),
+                ("restype", "container"),
+            ];
+            if !marker.is_empty() {
+                query.push(("marker", &marker));
+            }
+            let xml = self.get_path(prefix, "", &query).await?;
+            let page: EnumerationResults =
+                quick_xml::de::from_reader(xml.as_slice()).map_err(|source| Error::Listing {
+                    prefix: prefix.to_owned(),
+                    source,
+                })?;
+            marker = page.next_marker.clone();
+            pages.push(page);
+            if marker.is_empty() {
+                return Ok(pages);
+            }
+        }
+    }
+}
+
+// What Shared Key signs for a GET of url from account at date.
+fn string_to_sign(account: &str, date: &str, url: &str, query: &[(&str, &str)]) -> String {
+    // The path as sent, after the scheme and host.
+    let path = url
+        .split_once("://")
+        .and_then(|(_, rest)| rest.find('/').map(|i| &rest[i..]))
+        .unwrap_or("/");
+    let mut resource = format!("/{}{}", account, path);
+    let mut query = query.to_vec();
+    query.sort();
+    for (name, value) in query {
+        resource += &format!("\n{}:{}", name, value);
+    }
+    // VERB, eleven standard headers we never send, then the x-ms ones.
+    format!(
+        "GET\n\n\n\n\n\n\n\n\n\n\n\nx-ms-date:{}\nx-ms-version:{}\n{}",
+        date, API_VERSION, resource
+    )
+}
+
+fn signature(key: &[u8], string_to_sign: &str) -> String {
+    let mut mac = Hmac::<Sha256>::new_from_slice(key).unwrap();
+    mac.update(string_to_sign.as_bytes());
+    BASE64.encode(mac.finalize().into_bytes())
+}
+
+// Blob names go into the URL path as they are, apart from characters
+// that would change its meaning.
+fn encode_path(path: &str) -> String {
+    let mut encoded = String::new();
+    if !path.is_empty() {
+        encoded.push('/');
+    }
+    for b in path.bytes() {
+        match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' | b'/' => {
+                encoded.push(b as char)
+            }
+            _ => encoded += &format!("%{:02X}", b),
+        }
+    }
+    encoded
+}
+
+#[async_trait]
+impl BackupTarget for AzureTarget {
+    async fn get(&self, key: &str) -> Result<Vec<u8>, Error> {
+        self.get_path(key, key, &[]).await
+    }
+
+    async fn list_dirs(&self, prefix: &str) -> Result<Vec<String>, Error> {
+        let mut dirs = Vec::new();
+        for page in self.list(prefix).await? {
+            dirs.extend(page.blobs.prefixes.into_iter().map(|p| p.name));
+        }
+        Ok(dirs)
+    }
+
+    async fn list_files(&self, prefix: &str) -> Result<Vec<(String, u64)>, Error> {
+        let mut keys = Vec::new();
+        for page in self.list(prefix).await? {
+            keys.extend(
+                page.blobs
+                    .blobs
+                    .into_iter()
+                    .map(|b| (b.name, b.properties.content_length)),
+            );
+        }
+        Ok(keys)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Azurite's well-known development account key.
+    const KEY: &str =
+        "Eby8vdM02xNOcqFlqUwJPLlmEtlCDXJ1OUzFT50uSRZ6IFsuFq2UVErCz4I6tq/K1SZFPTOtr/KBHBeksoGMGw==";
+
+    #[test]
+    fn signs_listing() {
+        let sts = string_to_sign(
+            "devstoreaccount1",
+            "Fri, 16 Oct 2026 12:00:00 GMT",
+            "http://127.0.0.1:10000/devstoreaccount1/longhorn",
+            &[
+                ("restype", "container"),
+                ("comp", "list"),
+                ("prefix", "lh/backupstore/"),
+                ("delimiter", "/"),
+            ],
+        );
+        assert_eq!(
+            sts,
+            "GET\n\n\n\n\n\n\n\n\n\n\n\n\
+             x-ms-date:Fri, 16 Oct 2026 12:00:00 GMT\n\
+             x-ms-version:2021-08-06\n\
+             /devstoreaccount1/devstoreaccount1/longhorn\n\
+             comp:list\n\
+             delimiter:/\n\
+             prefix:lh/backupstore/\n\
+             restype:container"
+        );
+        assert_eq!(
+            signature(&BASE64.decode(KEY).unwrap(), &sts),
+            "O9t5x4WWbCPtcr+ymJNF2+JaNS0SNNJmaa4+85PyDkM="
+        );
+    }
+
+    #[test]
+    fn signs_path_without_query() {
+        let sts = string_to_sign(
+            "acct",
+            "Fri, 16 Oct 2026 12:00:00 GMT",
+            "https://acct.blob.core.windows.net/c/a%20b.cfg",
+            &[],
+        );
+        assert!(sts.ends_with("x-ms-version:2021-08-06\n/acct/c/a%20b.cfg"));
+    }
+
+    #[test]
+    fn encodes_path() {
+        assert_eq!(encode_path(""), "");
+        assert_eq!(
+            encode_path("lh/backupstore/volumes/7b/52/pvc-1/volume.cfg"),
+            "/lh/backupstore/volumes/7b/52/pvc-1/volume.cfg"
+        );
+        assert_eq!(encode_path("a b/c?d#e%f+g"), "/a%20b/c%3Fd%23e%25f%2Bg");
+    }
+}
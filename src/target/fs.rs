@@ -0,0 +1,68 @@
+use super::BackupTarget;
+use crate::error::Error;
+use async_trait::async_trait;
+use std::io::ErrorKind;
+use std::path::PathBuf;
+
+// A backupstore in a local directory, such as a mounted NFS target.
+pub struct FsTarget {
+    root: PathBuf,
+}
+
+impl FsTarget {
+    pub fn new(root: impl Into<PathBuf>) -> Self {
+        Self { root: root.into() }
+    }
+
+    // Directories (is_dir true) or files directly under prefix, as keys
+    // and sizes.
+    async fn list(&self, prefix: &str, is_dir: bool) -> Result<Vec<(String, u64)>, Error> {
+        let dir = self.root.join(prefix);
+        let mut entries = match tokio::fs::read_dir(&dir).await {
+            Ok(entries) => entries,
+            // Like an S3 prefix with nothing under it.
+            Err(e) if e.kind() == ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => return Err(Error::io(&dir)(e)),
+        };
+        let mut keys = Vec::new();
+        while let Some(entry) = entries.next_entry().await.map_err(Error::io(&dir))? {
+            let Ok(name) = entry.file_name().into_string() else {
+                continue;
+            };
+            // Follows symlinks, unlike entry.file_type().
+            let metadata = tokio::fs::metadata(entry.path())
+                .await
+                .map_err(Error::io(&entry.path()))?;
+            if is_dir && metadata.is_dir() {
+                keys.push((format!("{}{}/", prefix, name), 0));
+            } else if !is_dir && metadata.is_file() {
+                keys.push((format!("{}{}", prefix, name), metadata.len()));
+            }
+        }
+        keys.sort();
+        Ok(keys)
+    }
+}
+
+#[async_trait]
+impl BackupTarget for FsTarget {
+    async fn get(&self, key: &str) -> Result<Vec<u8>, Error> {
+        let path = self.root.join(key);
+        tokio::fs::read(&path).await.map_err(|e| {
+            if e.kind() == ErrorKind::NotFound {
+                Error::NotFound(format!("{} does not exist", path.display()))
+            } else {
+                Error::io(&path)(e)
+            }
+        })
+    }
+
+    async fn list_dirs(&self, prefix: &str) -> Result<Vec<String>, Error> {
+        let dirs = self.list(prefix, true).await?;
+        Ok(dirs.into_iter().map(|(dir, _)| dir).collect())
+    }
+
+    async fn list_files(&self, prefix: &str) -> Result<Vec<(String, u64)>, Error> {
+        self.list(prefix, false).await
+    }
+}
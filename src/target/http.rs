@@ -0,0 +1,144 @@
+use super::RetryPolicy;
+use crate::error::Error;
+use reqwest::{RequestBuilder, Response, StatusCode};
+
+// How a backup target's certificate is checked and how we identify
+// ourselves, for endpoints behind a private CA or requiring mutual TLS.
+#[derive(Clone, Debug, Default)]
+pub struct TlsOptions {
+    // PEM certificates to trust besides the system ones, as in Longhorn's
+    // AWS_CERT.
+    pub ca_certs: Option<Vec<u8>>,
+    // PEM certificate chain and PKCS#8 PEM private key to present.
+    pub client_identity: Option<(Vec<u8>, Vec<u8>)>,
+    // Accept any certificate for any host. Only for labs.
+    pub insecure: bool,
+}
+
+impl TlsOptions {
+    pub fn client(&self) -> Result<reqwest::Client, Error> {
+        let mut builder = reqwest::Client::builder();
+        if let Some(pem) = &self.ca_certs {
+            let certs =
+                reqwest::Certificate::from_pem_bundle(pem).map_err(|source| Error::Tls {
+                    what: "CA certificates",
+                    source,
+                })?;
+            if certs.is_empty() {
+                return Err(Error::Usage(
+                    "No PEM certificates found in the CA bundle".to_owned(),
+                ));
+            }
+            for cert in certs {
+                builder = builder.add_root_certificate(cert);
+            }
+        }
+        if let Some((cert, key)) = &self.client_identity {
+            let identity =
+                reqwest::Identity::from_pkcs8_pem(cert, key).map_err(|source| Error::Tls {
+                    what: "client certificate",
+                    source,
+                })?;
+            builder = builder.identity(identity);
+        }
+        if self.insecure {
+            builder = builder
+                .danger_accept_invalid_certs(true)
+                .danger_accept_invalid_hostnames(true);
+        }
+        builder.build().map_err(|source| Error::Tls {
+            what: "HTTP client",
+            source,
+        })
+    }
+}
+
+// A failed request: either no response at all or an unsuccessful status
+// with the service's error code.
+#[derive(Debug)]
+enum HttpError {
+    Request(reqwest::Error),
+    Status(StatusCode, String),
+}
+
+impl std::fmt::Display for HttpError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Request(e) => e.fmt(f),
+            Self::Status(status, code) => write!(f, "HTTP {} {}", status.as_u16(), code),
+        }
+    }
+}
+
+// Errors that another attempt might not run into: server-side failures,
+// throttling and broken connections. A reset on a reused connection
+// surfaces as a request error, one while reading the body as a decode
+// error.
+fn is_retryable(e: &HttpError) -> bool {
+    match e {
+        HttpError::Request(e) => {
+            e.is_connect() || e.is_timeout() || e.is_request() || e.is_body() || e.is_decode()
+        }
+        HttpError::Status(status, _) => {
+            status.is_server_error()
+                || *status == StatusCode::TOO_MANY_REQUESTS
+                || *status == StatusCode::REQUEST_TIMEOUT
+        }
+    }
+}
+
+// Sends the request request() builds until it succeeds or retry gives up,
+// and returns the body.
+pub(super) async fn get(
+    retry: &RetryPolicy,
+    what: &str,
+    request: impl Fn() -> RequestBuilder,
+) -> Result<Vec<u8>, Error> {
+    retry
+        .run(what, is_retryable, || async {
+            // Presigned URLs carry credentials, so keep them out of errors.
+            let response = request()
+                .send()
+                .await
+                .map_err(|e| HttpError::Request(e.without_url()))?;
+            let response = check_status(response).await?;
+            let body = response
+                .bytes()
+                .await
+                .map_err(|e| HttpError::Request(e.without_url()))?;
+            Ok(body.to_vec())
+        })
+        .await
+        .map_err(|e| match e {
+            HttpError::Request(source) => Error::Request {
+                key: what.to_owned(),
+                source,
+            },
+            HttpError::Status(status, code) => Error::Http {
+                key: what.to_owned(),
+                status: status.as_u16(),
+                code,
+            },
+        })
+}
+
+async fn check_status(response: Response) -> Result<Response, HttpError> {
+    let status = response.status();
+    if status.is_success() {
+        return Ok(response);
+    }
+    // Azure sends the code in a header, S3 only in the XML body.
+    if let Some(code) = response
+        .headers()
+        .get("x-ms-error-code")
+        .and_then(|v| v.to_str().ok())
+    {
+        return Err(HttpError::Status(status, code.to_owned()));
+    }
+    let body = response.text().await.unwrap_or_default();
+    let code = body
+        .split_once("<Code>")
+        .and_then(|(_, rest)| rest.split_once("</Code>"))
+        .map_or("", |(code, _)| code);
+    Err(HttpError::Status(status, code.to_owned()))
+}
@@ -0,0 +1,120 @@
+use super::{http, BackupTarget, RetryPolicy};
+use crate::error::Error;
+use async_trait::async_trait;
+use s3::serde_types::ListBucketResult;
+use s3::{Bucket, Region};
+use std::collections::HashMap;
+
+// Lifetime of the presigned URL for a single request and its retries.
+const PRESIGN_EXPIRY_SECS: u32 = 3600;
+
+// How the bucket goes into request URLs: https://endpoint/bucket/key or
+// https://bucket.endpoint/key.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, clap::ValueEnum)]
+pub enum Addressing {
+    // What Longhorn does: virtual-host style for AWS itself, path style
+    // for any other endpoint unless VIRTUAL_HOSTED_STYLE says otherwise.
+    Auto,
+    Path,
+    Virtual,
+}
+
+impl Addressing {
+    // virtual_hosted_style is Longhorn's VIRTUAL_HOSTED_STYLE setting.
+    pub fn path_style(self, region: &Region, virtual_hosted_style: bool) -> bool {
+        match self {
+            Self::Auto => matches!(region, Region::Custom { .. }) && !virtual_hosted_style,
+            Self::Path => true,
+            Self::Virtual => false,
+        }
+    }
+}
+
+// The bucket holding a backupstore, with retries around every request.
+// rust-s3 only signs the requests; they go out through our own client so
+// that its TLS settings apply.
+pub struct S3Target {
+    bucket: Box<Bucket>,
+    client: reqwest::Client,
+    retry: RetryPolicy,
+}
+
+impl S3Target {
+    pub fn new(bucket: Box<Bucket>, client: reqwest::Client, retry: RetryPolicy) -> Self {
+        Self {
+            bucket,
+            client,
+            retry,
+        }
+    }
+
+    async fn get_signed(
+        &self,
+        what: &str,
+        path: &str,
+        query: Option<HashMap<String, String>>,
+    ) -> Result<Vec<u8>, Error> {
+        let url = self
+            .bucket
+            .presign_get(path, PRESIGN_EXPIRY_SECS, query)
+            .await
+            .map_err(|source| Error::S3 {
+                key: what.to_owned(),
+                source,
+            })?;
+        http::get(&self.retry, what, || self.client.get(&url)).await
+    }
+
+    // Every page of a ListObjectsV2 of the keys directly under prefix.
+    async fn list(&self, prefix: &str) -> Result<Vec<ListBucketResult>, Error> {
+        let mut pages = Vec::new();
+        let mut token = None;
+        loop {
+            let mut query = HashMap::from([
+                ("list-type".to_owned(), "2".to_owned()),
+                ("prefix".to_owned(), prefix.to_owned()),
+                ("delimiter".to_owned(), "/".to_owned()),
+            ]);
+            if let Some(token) = token {
+                query.insert("continuation-token".to_owned(), token);
+            }
+            let xml = self.get_signed(prefix, "/", Some(query)).await?;
+            let page: ListBucketResult =
+                quick_xml::de::from_reader(xml.as_slice()).map_err(|source| Error::Listing {
+                    prefix: prefix.to_owned(),
+                    source,
+                })?;
+            token = page
+                .next_continuation_token
+                .clone()
+                .filter(|_| page.is_truncated);
+            pages.push(page);
+            if token.is_none() {
+                return Ok(pages);
+            }
+        }
+    }
+}
+
+#[async_trait]
+impl BackupTarget for S3Target {
+    async fn get(&self, key: &str) -> Result<Vec<u8>, Error> {
+        self.get_signed(key, key, None).await
+    }
+
+    async fn list_dirs(&self, prefix: &str) -> Result<Vec<String>, Error> {
+        let mut dirs = Vec::new();
+        for page in self.list(prefix).await? {
+            dirs.extend(page.common_prefixes.into_iter().flatten().map(|p| p.prefix));
+        }
+        Ok(dirs)
+    }
+
+    async fn list_files(&self, prefix: &str) -> Result<Vec<(String, u64)>, Error> {
+        let mut keys = Vec::new();
+        for page in self.list(prefix).await? {
+            keys.extend(page.contents.into_iter().map(|o| (o.key, o.size)));
+        }
+        Ok(keys)
+    }
+}
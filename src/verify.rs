@@ -0,0 +1,172 @@
+use crate::checksum::OnMismatch;
+use crate::compression::CompressionMethod;
+use crate::error::{error_chain, Error, ErrorKind};
+use crate::target::BackupTarget;
+use crate::{check_blocks, fetch_block, load_backup_cfg, Observer};
+use futures_util::{pin_mut, StreamExt};
+use serde::Serialize;
+use std::time::Instant;
+
+#[derive(Serialize, Debug)]
+pub struct BadBlock {
+    pub offset: u64,
+    pub checksum: String,
+    // missing, undecodable or corrupt.
+    pub problem: &'static str,
+    pub detail: String,
+}
+
+#[derive(Serialize, Default)]
+pub struct VerifySummary {
+    pub backup: String,
+    pub blocks: usize,
+    pub blocks_ok: usize,
+    pub bytes_downloaded: u64,
+    pub duration_secs: f64,
+    pub bad_blocks: Vec<BadBlock>,
+}
+
+impl VerifySummary {
+    // What to fail with if the backup is not restorable.
+    pub fn error(&self) -> Option<Error> {
+        (!self.bad_blocks.is_empty()).then(|| Error::VerifyFailed {
+            backup: self.backup.clone(),
+            bad_blocks: self.bad_blocks.len(),
+        })
+    }
+}
+
+// Fetches, decompresses and checks every block of the backup at
+// backup_cfg, in volume_dir, without writing anything. Bad blocks end up
+// in the summary; an error means verification itself failed.
+pub async fn verify(
+    target: &dyn BackupTarget,
+    volume_dir: &str,
+    backup_cfg: &str,
+    jobs: usize,
+    observer: &mut dyn Observer,
+) -> Result<VerifySummary, Error> {
+    let start = Instant::now();
+    let index = load_backup_cfg(target, backup_cfg).await?;
+    check_blocks(&index.Blocks, index.Size)?;
+    let compression = index.CompressionMethod.parse::<CompressionMethod>()?;
+
+    observer.start(index.Blocks.len());
+    let mut summary = VerifySummary {
+        backup: backup_cfg.to_owned(),
+        blocks: index.Blocks.len(),
+        ..Default::default()
+    };
+    // Unlike get_backup, keeps going past a bad block.
+    let b = futures_util::stream::iter(&index.Blocks)
+        .map(|block| async move {
+            let result =
+                fetch_block(target, volume_dir, block, compression, OnMismatch::Report).await;
+            (block, result)
+        })
+        .buffer_unordered(jobs);
+    pin_mut!(b);
+
+    while let Some((block, result)) = b.next().await {
+        let (stored_len, len) = result
+            .as_ref()
+            .map_or((0, 0), |f| (f.stored_len, f.data.len()));
+        observer.block(stored_len, len);
+        summary.bytes_downloaded += stored_len as u64;
+        let (problem, detail) = match result {
+            Ok(fetched) => match fetched.mismatch {
+                None => {
+                    summary.blocks_ok += 1;
+                    continue;
+                }
+                Some(mismatch) => ("corrupt", format!("found checksum {}", mismatch.found)),
+            },
+            Err(e @ Error::Decode { .. }) => ("undecodable", error_chain(&e)),
+            Err(e) if e.kind() == ErrorKind::NotFound => ("missing", e.to_string()),
+            // Anything else, such as the target being unreachable, says
+            // nothing about the backup.
+            Err(e) => return Err(e),
+        };
+        let bad = BadBlock {
+            offset: block.Offset,
+            checksum: block.BlockChecksum.clone(),
+            problem,
+            detail,
+        };
+        observer.bad_block(&bad);
+        summary.bad_blocks.push(bad);
+    }
+    summary.duration_secs = start.elapsed().as_secs_f64();
+    summary.bad_blocks.sort_by_key(|b| b.offset);
+    Ok(summary)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backupstore::block_key;
+    use crate::checksum::longhorn_checksum;
+    use crate::config::BackupBlock;
+    use crate::fixture::{compress, Backupstore};
+    use crate::BLOCK_SIZE;
+
+    #[tokio::test]
+    async fn sorts_out_bad_blocks() {
+        let store = Backupstore::new("verify");
+        let lz4 = CompressionMethod::Lz4;
+        let good = store.put_block(0, b"good", lz4);
+        // Never stored.
+        let missing = BackupBlock {
+            Offset: BLOCK_SIZE,
+            BlockChecksum: longhorn_checksum(b"missing"),
+        };
+        let undecodable = BackupBlock {
+            Offset: 2 * BLOCK_SIZE,
+            BlockChecksum: longhorn_checksum(b"undecodable"),
+        };
+        store.put(
+            &block_key(&store.volume_dir, &undecodable.BlockChecksum),
+            b"not lz4",
+        );
+        let corrupt = BackupBlock {
+            Offset: 3 * BLOCK_SIZE,
+            BlockChecksum: longhorn_checksum(b"corrupt"),
+        };
+        store.put(
+            &block_key(&store.volume_dir, &corrupt.BlockChecksum),
+            &compress(lz4, b"bit rot"),
+        );
+        let cfg = store.put_backup(
+            "b",
+            4 * BLOCK_SIZE,
+            "lz4",
+            vec![good, missing, undecodable, corrupt],
+        );
+
+        let summary = verify(&store.target, &store.volume_dir, &cfg, 2, &mut ())
+            .await
+            .unwrap();
+        assert_eq!((summary.blocks, summary.blocks_ok), (4, 1));
+        let bad: Vec<_> = summary
+            .bad_blocks
+            .iter()
+            .map(|b| (b.offset, b.problem))
+            .collect();
+        assert_eq!(
+            bad,
+            [
+                (BLOCK_SIZE, "missing"),
+                (2 * BLOCK_SIZE, "undecodable"),
+                (3 * BLOCK_SIZE, "corrupt"),
+            ]
+        );
+        assert_eq!(
+            summary.bad_blocks[2].detail,
+            format!("found checksum {}", longhorn_checksum(b"bit rot"))
+        );
+        assert!(matches!(
+            summary.error(),
+            Some(Error::VerifyFailed { bad_blocks: 3, .. })
+        ));
+    }
+}
@@ -0,0 +1,204 @@
+use std::fs::{File, OpenOptions};
+use std::io::{Seek, SeekFrom, Write};
+use std::os::unix::fs::{FileTypeExt, MetadataExt};
+use std::os::unix::io::AsRawFd;
+use std::path::Path;
+use std::time::UNIX_EPOCH;
+
+// What to do on a block device with the regions the backup has no
+// blocks for. A file starts out as holes so there is nothing to do.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, clap::ValueEnum)]
+pub enum GapFill {
+    Leave,
+    Zero,
+    Discard,
+}
+
+pub struct Destination {
+    file: File,
+    punch_zeroes: bool,
+    // Logical sector size when writing to a block device.
+    sector_size: Option<u64>,
+}
+
+impl Destination {
+    // The file is sized to the whole volume up front; anything not
+    // written afterwards stays a hole.
+    pub fn create(path: &Path, size: u64, punch_zeroes: bool) -> std::io::Result<Self> {
+        let file = File::create(path)?;
+        file.set_len(size)?;
+        Ok(Self {
+            file,
+            punch_zeroes,
+            sector_size: None,
+        })
+    }
+
+    // Like create, but keeps what an earlier restore already wrote, so
+    // the file must still be there.
+    pub fn open(path: &Path, size: u64, punch_zeroes: bool) -> std::io::Result<Self> {
+        let file = OpenOptions::new().write(true).open(path)?;
+        file.set_len(size)?;
+        Ok(Self {
+            file,
+            punch_zeroes,
+            sector_size: None,
+        })
+    }
+
+    pub fn open_device(path: &Path, size: u64, punch_zeroes: bool) -> std::io::Result<Self> {
+        let mut file = OpenOptions::new().write(true).open(path)?;
+        if !file.metadata()?.file_type().is_block_device() {
+            return Err(std::io::Error::other(format!(
+                "{} is not a block device",
+                path.display()
+            )));
+        }
+        let capacity = file.seek(SeekFrom::End(0))?;
+        if capacity < size {
+            return Err(std::io::Error::other(format!(
+                "{} holds {} bytes but the volume is {} bytes",
+                path.display(),
+                capacity,
+                size
+            )));
+        }
+        let sector_size = sector_size(&file)?;
+        Ok(Self {
+            file,
+            punch_zeroes,
+            sector_size: Some(sector_size),
+        })
+    }
+
+    pub fn write_block(&mut self, offset: u64, data: &[u8]) -> std::io::Result<()> {
+        self.check_aligned(offset, data.len() as u64)?;
+        if self.punch_zeroes && data.iter().all(|&b| b == 0) {
+            match punch_hole(&self.file, offset, data.len() as u64) {
+                Ok(()) => return Ok(()),
+                // Not every filesystem can do it; fall back to writing.
+                Err(e) if e.raw_os_error() == Some(libc::EOPNOTSUPP) => (),
+                Err(e) => return Err(e),
+            }
+        }
+        self.file.seek(SeekFrom::Start(offset))?;
+        self.file.write_all(data)
+    }
+
+    // Only meaningful for block devices, which may hold old data.
+    pub fn fill_gaps(&mut self, gaps: &[(u64, u64)], how: GapFill) -> std::io::Result<()> {
+        if self.sector_size.is_none() {
+            return Ok(());
+        }
+        for &(offset, len) in gaps {
+            self.check_aligned(offset, len)?;
+            match how {
+                GapFill::Leave => (),
+                GapFill::Zero => self.zero_range(offset, len)?,
+                GapFill::Discard => block_ioctl(&self.file, BLKDISCARD, offset, len)?,
+            }
+        }
+        Ok(())
+    }
+
+    // Tells this file or device apart from one that replaced it at the
+    // same path. The creation time, where the filesystem keeps one,
+    // catches a new file that got the inode number of the old one.
+    pub fn identity(&self) -> std::io::Result<String> {
+        let metadata = self.file.metadata()?;
+        if metadata.file_type().is_block_device() {
+            return Ok(format!("rdev={}", metadata.rdev()));
+        }
+        let mut identity = format!("dev={} ino={}", metadata.dev(), metadata.ino());
+        if let Some(created) = metadata
+            .created()
+            .ok()
+            .and_then(|t| t.duration_since(UNIX_EPOCH).ok())
+        {
+            identity += &format!(" created={}", created.as_nanos());
+        }
+        Ok(identity)
+    }
+
+    pub fn sync(&self) -> std::io::Result<()> {
+        self.file.sync_data()
+    }
+
+    // Everything must be on disk before the journal that says otherwise
+    // goes away.
+    pub fn finish(self) -> std::io::Result<()> {
+        self.file.sync_all()
+    }
+
+    fn check_aligned(&self, offset: u64, len: u64) -> std::io::Result<()> {
+        match self.sector_size {
+            Some(sector) if !offset.is_multiple_of(sector) || !len.is_multiple_of(sector) => {
+                Err(std::io::Error::other(format!(
+                    "Write of {} bytes at offset {} is not aligned to the {} byte sectors of the device",
+                    len, offset, sector
+                )))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    fn zero_range(&mut self, offset: u64, len: u64) -> std::io::Result<()> {
+        match block_ioctl(&self.file, BLKZEROOUT, offset, len) {
+            Ok(()) => return Ok(()),
+            Err(e) if e.raw_os_error() != Some(libc::EOPNOTSUPP) => return Err(e),
+            Err(_) => (),
+        }
+        let zeroes = vec![0; 1 << 20];
+        self.file.seek(SeekFrom::Start(offset))?;
+        let mut left = len;
+        while left > 0 {
+            let n = left.min(zeroes.len() as u64);
+            self.file.write_all(&zeroes[..n as usize])?;
+            left -= n;
+        }
+        Ok(())
+    }
+}
+
+// From linux/fs.h.
+const BLKSSZGET: u32 = 0x1268;
+const BLKDISCARD: u32 = 0x1277;
+const BLKZEROOUT: u32 = 0x127f;
+
+fn sector_size(file: &File) -> std::io::Result<u64> {
+    let mut size: libc::c_int = 0;
+    if unsafe { libc::ioctl(file.as_raw_fd(), BLKSSZGET as _, &mut size) } != 0 {
+        return Err(std::io::Error::last_os_error());
+    }
+    Ok(size as u64)
+}
+
+fn block_ioctl(file: &File, request: u32, offset: u64, len: u64) -> std::io::Result<()> {
+    let range: [u64; 2] = [offset, len];
+    if unsafe { libc::ioctl(file.as_raw_fd(), request as _, &range) } != 0 {
+        return Err(std::io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+#[cfg(target_os = "linux")]
+fn punch_hole(file: &File, offset: u64, len: u64) -> std::io::Result<()> {
+    let r = unsafe {
+        libc::fallocate(
+            file.as_raw_fd(),
+            libc::FALLOC_FL_PUNCH_HOLE | libc::FALLOC_FL_KEEP_SIZE,
+            offset as libc::off_t,
+            len as libc::off_t,
+        )
+    };
+    if r == 0 {
+        Ok(())
+    } else {
+        Err(std::io::Error::last_os_error())
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+fn punch_hole(_file: &File, _offset: u64, _len: u64) -> std::io::Result<()> {
+    Err(std::io::Error::from_raw_os_error(libc::EOPNOTSUPP))
+}